@@ -1,7 +1,6 @@
-use ::anyhow::{Context, Result};
 use ::async_trait::async_trait;
 use ::crdt_enc::{
-    CoreSubHandle, Info,
+    CoreSubHandle, Error, Info, Result,
     key_cryptor::Keys,
     utils::{
         LockBox, VersionBytes, decode_version_bytes_mvreg_custom_phf,
@@ -85,7 +84,12 @@ impl crdt_enc::key_cryptor::KeyCryptor for KeyHandler {
                 data.remote_meta.merge(new_remote_meta);
             }
 
-            let core = dyn_clone::clone_box(&**data.core.as_ref().context("core is none")?);
+            let core = dyn_clone::clone_box(
+                &**data
+                    .core
+                    .as_ref()
+                    .ok_or_else(|| Error::key_cryptor("core is none"))?,
+            );
             Ok((data.remote_meta.clone(), core))
         })?;
 
@@ -106,7 +110,12 @@ impl crdt_enc::key_cryptor::KeyCryptor for KeyHandler {
 
     async fn set_keys(&self, new_keys: ReadCtx<Keys, Uuid>) -> Result<()> {
         let (mut rm, core) = self.data.try_with(|data| {
-            let core = dyn_clone::clone_box(&**data.core.as_ref().context("core is none")?);
+            let core = dyn_clone::clone_box(
+                &**data
+                    .core
+                    .as_ref()
+                    .ok_or_else(|| Error::key_cryptor("core is none"))?,
+            );
             Ok((data.remote_meta.clone(), core))
         })?;
 
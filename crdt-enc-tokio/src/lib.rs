@@ -1,20 +1,30 @@
 use ::anyhow::{Context, Error, Result, ensure};
 use ::async_trait::async_trait;
 use ::bytes::Buf;
-use ::crdt_enc::utils::{VersionBytes, VersionBytesRef};
+use ::crdt_enc::{
+    Error as CoreError, Result as CoreResult,
+    storage::StorageEvent,
+    utils::{VersionBytes, VersionBytesRef},
+};
 use ::futures::{
+    channel::mpsc,
     future::{Either, TryFutureExt},
-    stream::{self, Stream, StreamExt, TryStreamExt},
+    stream::{self, BoxStream, Stream, StreamExt, TryStreamExt},
 };
+use ::notify::{PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
 use ::std::{
+    ffi::OsString,
     fmt::Debug,
+    ops::Range,
     path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 use ::tiny_keccak::{Hasher, Sha3};
 use ::tokio::{
     fs,
     io::{self, AsyncWriteExt},
+    time,
 };
 use ::tokio_stream::wrappers::ReadDirStream;
 use ::uuid::Uuid;
@@ -23,6 +33,35 @@ use ::uuid::Uuid;
 pub struct Storage {
     local_path: PathBuf,
     remote_path: PathBuf,
+    /// Dir of the local cache, `local_path` for the default document
+    local_document_path: PathBuf,
+    /// Dir of the states and ops, `remote_path` for the default document
+    document_path: PathBuf,
+    watch_options: WatchOptions,
+}
+
+/// Options for watching the remote dir, see `crdt_enc::storage::Storage::watch`.
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Changes are only reported after the remote dir was quiet for this long, so files that are
+    /// still being synced aren't read half-written
+    pub debounce: Duration,
+    /// Changes are reported at the latest this long after the first one, even if the remote dir
+    /// doesn't get quiet
+    pub max_delay: Duration,
+    /// Poll interval of the fallback watcher, used if the platform's watcher (e.g. inotify) is
+    /// not available
+    pub poll_interval: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> WatchOptions {
+        WatchOptions {
+            debounce: Duration::from_millis(500),
+            max_delay: Duration::from_secs(5),
+            poll_interval: Duration::from_secs(10),
+        }
+    }
 }
 
 impl Storage {
@@ -39,43 +78,285 @@ impl Storage {
         );
 
         Ok(Storage {
+            local_document_path: local_path.clone(),
             local_path,
+            document_path: remote_path.clone(),
             remote_path,
+            watch_options: WatchOptions::default(),
         })
     }
+
+    pub fn with_watch_options(mut self, watch_options: WatchOptions) -> Storage {
+        self.watch_options = watch_options;
+        self
+    }
 }
 
 #[async_trait]
 impl crdt_enc::storage::Storage for Storage {
-    async fn load_local_meta(&self) -> Result<Option<VersionBytes>> {
+    fn document(&self, id: Uuid) -> CoreResult<Storage> {
+        let mut local_document_path = self.local_path.join("documents");
+        local_document_path.push(id.to_string());
+        let mut document_path = self.remote_path.join("documents");
+        document_path.push(id.to_string());
+
+        Ok(Storage {
+            local_path: self.local_path.clone(),
+            remote_path: self.remote_path.clone(),
+            local_document_path,
+            document_path,
+            watch_options: self.watch_options.clone(),
+        })
+    }
+
+    async fn list_documents(&self) -> CoreResult<Vec<Uuid>> {
+        let documents_dir = self.remote_path.join("documents");
+        read_dir_optional_dirs(documents_dir)
+            .map_err(|err| err.context("failed listing documents"))
+            .and_then(|entry| async move {
+                let id = entry.file_name();
+                let id = id.to_str().with_context(|| {
+                    format!("error converting document dir name {:?} to string", id)
+                })?;
+                let id = Uuid::from_str(id).with_context(|| {
+                    format!("error converting document dir string {} into uuid", id)
+                })?;
+                Ok(id)
+            })
+            .try_collect()
+            .await
+            .map_err(CoreError::storage)
+    }
+
+    async fn load_local_meta(&self) -> CoreResult<Option<VersionBytes>> {
         let path = self.local_path.join("meta-data.msgpack");
         let bytes = read_file_optional(&path)
             .await
-            .with_context(|| format!("failed reading local meta file {}", path.display()))?;
+            .with_context(|| format!("failed reading local meta file {}", path.display()))
+            .map_err(CoreError::storage)?;
         bytes
             .map(|bytes| {
-                let lm = VersionBytes::deserialize(&bytes).with_context(|| {
-                    format!("failed parsing local meta file {}", path.display())
-                })?;
+                let lm = VersionBytes::deserialize(&bytes)
+                    .with_context(|| format!("failed parsing local meta file {}", path.display()))
+                    .map_err(CoreError::decode)?;
                 Ok(lm)
             })
             .transpose()
     }
 
-    async fn store_local_meta(&self, meta: VersionBytes) -> Result<()> {
+    async fn store_local_meta(&self, meta: VersionBytes) -> CoreResult<()> {
         fs::create_dir_all(&self.local_path)
             .await
-            .with_context(|| format!("failed creating local dir {:?}", self.local_path))?;
+            .with_context(|| format!("failed creating local dir {:?}", self.local_path))
+            .map_err(CoreError::storage)?;
 
         let path = self.local_path.join("meta-data.msgpack");
         // TODO: catch concurrent writes, locking?
         write_file(&path, meta.buf())
             .await
-            .with_context(|| format!("failed writing local meta file {:?}", path))?;
+            .with_context(|| format!("failed writing local meta file {:?}", path))
+            .map_err(CoreError::storage)?;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn load_local_cache(&self) -> CoreResult<Option<VersionBytes>> {
+        let path = self.local_document_path.join("cache.msgpack");
+        let bytes = read_file_optional(&path)
+            .await
+            .with_context(|| format!("failed reading local cache file {}", path.display()))
+            .map_err(CoreError::storage)?;
+        bytes
+            .map(|bytes| {
+                let cache = VersionBytes::deserialize(&bytes)
+                    .with_context(|| format!("failed parsing local cache file {}", path.display()))
+                    .map_err(CoreError::decode)?;
+                Ok(cache)
+            })
+            .transpose()
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(bytes = cache.as_ref().len()), err)
+    )]
+    async fn store_local_cache(&self, cache: VersionBytes) -> CoreResult<()> {
+        fs::create_dir_all(&self.local_document_path)
+            .await
+            .with_context(|| format!("failed creating local dir {:?}", self.local_document_path))
+            .map_err(CoreError::storage)?;
+
+        let path = self.local_document_path.join("cache.msgpack");
+        write_file_atomic(&path, cache.buf())
+            .await
+            .with_context(|| format!("failed writing local cache file {:?}", path))
+            .map_err(CoreError::storage)?;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn load_local_high_water(&self) -> CoreResult<Option<VersionBytes>> {
+        let path = self.local_document_path.join("high-water.msgpack");
+        let bytes = read_file_optional(&path)
+            .await
+            .with_context(|| format!("failed reading high-water file {}", path.display()))
+            .map_err(CoreError::storage)?;
+        bytes
+            .map(|bytes| {
+                let high_water = VersionBytes::deserialize(&bytes)
+                    .with_context(|| format!("failed parsing high-water file {}", path.display()))
+                    .map_err(CoreError::decode)?;
+                Ok(high_water)
+            })
+            .transpose()
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn store_local_high_water(&self, high_water: VersionBytes) -> CoreResult<()> {
+        fs::create_dir_all(&self.local_document_path)
+            .await
+            .with_context(|| format!("failed creating local dir {:?}", self.local_document_path))
+            .map_err(CoreError::storage)?;
+
+        let path = self.local_document_path.join("high-water.msgpack");
+        write_file_atomic(&path, high_water.buf())
+            .await
+            .with_context(|| format!("failed writing high-water file {:?}", path))
+            .map_err(CoreError::storage)?;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn load_local_key_pins(&self) -> CoreResult<Option<VersionBytes>> {
+        let path = self.local_document_path.join("key-pins.msgpack");
+        let bytes = read_file_optional(&path)
+            .await
+            .with_context(|| format!("failed reading key pins file {}", path.display()))
+            .map_err(CoreError::storage)?;
+        bytes
+            .map(|bytes| {
+                let key_pins = VersionBytes::deserialize(&bytes)
+                    .with_context(|| format!("failed parsing key pins file {}", path.display()))
+                    .map_err(CoreError::decode)?;
+                Ok(key_pins)
+            })
+            .transpose()
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn store_local_key_pins(&self, key_pins: VersionBytes) -> CoreResult<()> {
+        fs::create_dir_all(&self.local_document_path)
+            .await
+            .with_context(|| format!("failed creating local dir {:?}", self.local_document_path))
+            .map_err(CoreError::storage)?;
+
+        let path = self.local_document_path.join("key-pins.msgpack");
+        write_file_atomic(&path, key_pins.buf())
+            .await
+            .with_context(|| format!("failed writing key pins file {:?}", path))
+            .map_err(CoreError::storage)?;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn load_journal(&self) -> CoreResult<Vec<(u64, CoreResult<VersionBytes>)>> {
+        let journal_dir = self.local_document_path.join("journal");
+        read_dir_optional_files(journal_dir)
+            .map_err(|err| CoreError::storage(err.context("failed listing journal entries")))
+            .try_filter_map(|entry| async move {
+                let file_name = entry.file_name();
+                if file_name.to_string_lossy().starts_with('.') {
+                    // temporary file of an interrupted write
+                    return Ok(None);
+                }
+
+                let path = entry.path();
+                let version = file_name
+                    .to_str()
+                    .and_then(|name| u64::from_str(name).ok())
+                    .with_context(|| {
+                        format!("failed parsing version of journal file {}", path.display())
+                    })
+                    .map_err(CoreError::storage)?;
+                let bytes = fs::read(&path)
+                    .await
+                    .with_context(|| format!("failed reading journal file {}", path.display()))
+                    .map_err(CoreError::storage)?;
+                let block = VersionBytes::deserialize(&bytes)
+                    .with_context(|| format!("failed parsing journal file {}", path.display()))
+                    .map_err(CoreError::decode);
+                Ok(Some((version, block)))
+            })
+            .try_collect()
+            .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(version = version, bytes = bytes.as_ref().len()),
+            err
+        )
+    )]
+    async fn store_journal_op(&self, version: u64, bytes: VersionBytes) -> CoreResult<()> {
+        let mut path = self.local_document_path.join("journal");
+
+        fs::create_dir_all(&path)
+            .await
+            .with_context(|| format!("failed creating journal dir {:?}", path))
+            .map_err(CoreError::storage)?;
+
+        path.push(version.to_string());
+        write_file_atomic(&path, bytes.buf())
+            .await
+            .with_context(|| format!("failed writing journal file {:?}", path))
+            .map_err(CoreError::storage)?;
         Ok(())
     }
 
-    async fn list_remote_meta_names(&self) -> Result<Vec<String>> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(version = version), err)
+    )]
+    async fn remove_journal_op(&self, version: u64) -> CoreResult<()> {
+        let mut path = self.local_document_path.join("journal");
+        path.push(version.to_string());
+
+        remove_file_optional(&path)
+            .await
+            .with_context(|| format!("failed removing journal file {}", path.display()))
+            .map_err(CoreError::storage)
+    }
+
+    fn content_name(&self, data: &VersionBytes) -> Option<String> {
+        Some(content_name(&data.as_version_bytes_ref()))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn list_remote_meta_names(&self) -> CoreResult<Vec<String>> {
         let meta_dir = self.remote_path.join("meta");
         read_dir_optional_files(meta_dir)
             .map_err(|err| err.context("failed listing remote meta entries"))
@@ -90,36 +371,62 @@ impl crdt_enc::storage::Storage for Storage {
             })
             .try_collect()
             .await
+            .map_err(CoreError::storage)
     }
 
-    async fn load_remote_metas(&self, names: Vec<String>) -> Result<Vec<(String, VersionBytes)>> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(count = names.len()), err)
+    )]
+    async fn load_remote_metas(
+        &self,
+        names: Vec<String>,
+    ) -> CoreResult<Vec<(String, VersionBytes)>> {
         let futs = names.into_iter().map(|name| {
             let mut path = self.remote_path.join("meta");
             path.push(&name);
             let path = path;
 
             async move {
-                let bytes = fs::read(&path).await.with_context(|| {
-                    format!("failed reading remote meta file {}", path.display())
-                })?;
-                let rm = VersionBytes::deserialize(&bytes).with_context(|| {
-                    format!("failed parsing remote meta file {}", path.display())
-                })?;
-                Ok((name, rm))
+                let bytes = fs::read(&path)
+                    .await
+                    .with_context(|| format!("failed reading remote meta file {}", path.display()))
+                    .map_err(CoreError::storage)?;
+                let rm = VersionBytes::deserialize(&bytes)
+                    .with_context(|| format!("failed parsing remote meta file {}", path.display()))
+                    .map_err(CoreError::decode)?;
+                CoreResult::Ok((name, rm))
             }
         });
 
-        stream::iter(futs).buffer_unordered(32).try_collect().await
+        let blocks: Vec<_> = stream::iter(futs)
+            .buffer_unordered(32)
+            .try_collect()
+            .await?;
+
+        #[cfg(feature = "tracing")]
+        trace_loaded(blocks.iter().map(|(_, block)| block));
+
+        Ok(blocks)
     }
 
-    async fn store_remote_meta(&self, meta: VersionBytes) -> Result<String> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(bytes = meta.as_ref().len()), err)
+    )]
+    async fn store_remote_meta(&self, meta: VersionBytes) -> CoreResult<String> {
         let meta_dir = self.remote_path.join("meta");
         write_content_addressible_file(&meta_dir, &meta.as_version_bytes_ref())
             .await
             .context("failed writing remote meta file")
+            .map_err(CoreError::storage)
     }
 
-    async fn remove_remote_metas(&self, names: Vec<String>) -> Result<()> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(count = names.len()), err)
+    )]
+    async fn remove_remote_metas(&self, names: Vec<String>) -> CoreResult<()> {
         let futs = names.into_iter().map(|name| {
             let mut path = self.remote_path.join("meta");
             path.push(&name);
@@ -132,11 +439,19 @@ impl crdt_enc::storage::Storage for Storage {
             }
         });
 
-        stream::iter(futs).buffer_unordered(32).try_collect().await
+        stream::iter(futs)
+            .buffer_unordered(32)
+            .try_collect()
+            .await
+            .map_err(CoreError::storage)
     }
 
-    async fn list_state_names(&self) -> Result<Vec<String>> {
-        let states_dir = self.remote_path.join("states");
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn list_state_names(&self) -> CoreResult<Vec<String>> {
+        let states_dir = self.document_path.join("states");
         read_dir_optional_files(states_dir)
             .map_err(|err| err.context("failed listing states"))
             .and_then(|entry| async move {
@@ -150,39 +465,67 @@ impl crdt_enc::storage::Storage for Storage {
             })
             .try_collect()
             .await
+            .map_err(CoreError::storage)
     }
 
-    async fn load_states(&self, names: Vec<String>) -> Result<Vec<(String, VersionBytes)>> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(count = names.len()), err)
+    )]
+    async fn load_states(
+        &self,
+        names: Vec<String>,
+    ) -> CoreResult<Vec<(String, CoreResult<VersionBytes>)>> {
         let futs = names.into_iter().map(|name| {
-            let mut path = self.remote_path.join("states");
+            let mut path = self.document_path.join("states");
             path.push(&name);
             let path = path;
 
             async move {
                 let block = fs::read(&path)
                     .await
-                    .with_context(|| format!("failed reading state file {}", path.display()))?;
+                    .with_context(|| format!("failed reading state file {}", path.display()))
+                    .map_err(CoreError::storage)?;
+                // a corrupt file doesn't fail the others
                 let block = VersionBytes::deserialize(&block)
-                    .with_context(|| format!("failed parsing state file {}", path.display()))?;
-                Ok((name, block))
+                    .with_context(|| format!("failed parsing state file {}", path.display()))
+                    .map_err(CoreError::decode);
+                CoreResult::Ok((name, block))
             }
         });
 
-        stream::iter(futs).buffer_unordered(32).try_collect().await
+        let blocks: Vec<_> = stream::iter(futs)
+            .buffer_unordered(32)
+            .try_collect()
+            .await?;
+
+        #[cfg(feature = "tracing")]
+        trace_loaded(blocks.iter().filter_map(|(_, block)| block.as_ref().ok()));
+
+        Ok(blocks)
     }
 
-    async fn store_state(&self, bytes: VersionBytes) -> Result<String> {
-        let states_dir = self.remote_path.join("states");
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(bytes = bytes.as_ref().len()), err)
+    )]
+    async fn store_state(&self, bytes: VersionBytes) -> CoreResult<String> {
+        let states_dir = self.document_path.join("states");
         write_content_addressible_file(&states_dir, &bytes.as_version_bytes_ref())
             .await
             .context("failed writing state file")
+            .map_err(CoreError::storage)
     }
 
-    async fn remove_states(&self, names: Vec<String>) -> Result<Vec<String>> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(count = names.len()), err)
+    )]
+    async fn remove_states(&self, names: Vec<String>) -> CoreResult<Vec<String>> {
         let futs = names
             .iter()
             .map(|name| {
-                let mut path = self.remote_path.join("states");
+                let mut path = self.document_path.join("states");
                 path.push(&name);
                 let path = path;
 
@@ -196,13 +539,18 @@ impl crdt_enc::storage::Storage for Storage {
 
         stream::iter(futs)
             .try_for_each_concurrent(32, |f| f)
-            .await?;
+            .await
+            .map_err(CoreError::storage)?;
 
         Ok(names)
     }
 
-    async fn list_op_actors(&self) -> Result<Vec<Uuid>> {
-        let ops_dir = self.remote_path.join("ops");
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    async fn list_op_actors(&self) -> CoreResult<Vec<Uuid>> {
+        let ops_dir = self.document_path.join("ops");
         read_dir_optional_dirs(ops_dir)
             .map_err(|err| err.context("failed listing actors"))
             .and_then(|entry| async move {
@@ -217,20 +565,54 @@ impl crdt_enc::storage::Storage for Storage {
             })
             .try_collect()
             .await
+            .map_err(CoreError::storage)
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(actor = %actor), err)
+    )]
+    async fn list_op_versions(&self, actor: Uuid) -> CoreResult<Vec<u64>> {
+        let mut actor_dir = self.document_path.join("ops");
+        actor_dir.push(actor.to_string());
+
+        read_dir_optional_files(actor_dir)
+            .map_err(move |err| err.context(format!("failed listing ops of actor {}", actor)))
+            .try_filter_map(|entry| async move {
+                // skips temporary files of sync tools
+                let version = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| u64::from_str(name).ok());
+                Ok(version)
+            })
+            .try_collect()
+            .await
+            .map_err(CoreError::storage)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(actors = actor_first_versions.len()),
+            err
+        )
+    )]
     async fn load_ops(
         &self,
         actor_first_versions: Vec<(Uuid, u64)>,
-    ) -> Result<Vec<(Uuid, u64, VersionBytes)>> {
+    ) -> CoreResult<Vec<(Uuid, u64, CoreResult<VersionBytes>)>> {
         async fn get_entry(
             path: &Path,
             actor: Uuid,
             version: u64,
-        ) -> Result<Option<(Uuid, u64, VersionBytes)>> {
+        ) -> CoreResult<Option<(Uuid, u64, CoreResult<VersionBytes>)>> {
             let bytes = read_file_optional(path)
                 .await
-                .with_context(|| format!("failed reading op file {}", path.display()))?;
+                .with_context(|| format!("failed reading op file {}", path.display()))
+                .map_err(CoreError::storage)?;
 
             let bytes = if let Some(bytes) = bytes {
                 bytes
@@ -238,15 +620,17 @@ impl crdt_enc::storage::Storage for Storage {
                 return Ok(None);
             };
 
+            // a corrupt file doesn't fail the others
             let data = VersionBytes::deserialize(&bytes)
-                .with_context(|| format!("failed parsing op file {}", path.display()))?;
+                .with_context(|| format!("failed parsing op file {}", path.display()))
+                .map_err(CoreError::decode);
 
             Ok(Some((actor, version, data)))
         }
 
-        let path = self.remote_path.join("ops");
+        let path = self.document_path.join("ops");
 
-        stream::iter(actor_first_versions)
+        let blocks: Vec<_> = stream::iter(actor_first_versions)
             .map(move |(actor, first_version)| {
                 let path = path.join(actor.to_string());
 
@@ -268,57 +652,282 @@ impl crdt_enc::storage::Storage for Storage {
                         .try_collect::<Vec<_>>()
                         .await?;
 
-                    Result::<_, Error>::Ok(stream::iter(ops).map(Ok))
+                    CoreResult::Ok(stream::iter(ops).map(Ok))
                 }
             })
             .buffer_unordered(32)
             .try_flatten()
             .try_collect()
-            .await
+            .await?;
+
+        #[cfg(feature = "tracing")]
+        trace_loaded(
+            blocks
+                .iter()
+                .filter_map(|(_, _, block)| block.as_ref().ok()),
+        );
+
+        Ok(blocks)
     }
 
-    async fn store_ops(&self, actor: Uuid, version: u64, bytes: VersionBytes) -> Result<()> {
-        let mut path = self.remote_path.join("ops");
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(actor = %actor, version = version, bytes = bytes.as_ref().len()),
+            err
+        )
+    )]
+    async fn store_ops(&self, actor: Uuid, version: u64, bytes: VersionBytes) -> CoreResult<()> {
+        let mut path = self.document_path.join("ops");
         path.push(actor.to_string());
 
         fs::create_dir_all(&path)
             .await
-            .with_context(|| format!("failed creating op dir {:?} for actor {}", path, actor))?;
+            .with_context(|| format!("failed creating op dir {:?} for actor {}", path, actor))
+            .map_err(CoreError::storage)?;
 
         path.push(version.to_string());
-        write_new_file(&path, bytes.buf())
-            .await
-            .with_context(|| format!("failed writing ops file {:?}", path))?;
+        let res = write_new_file(&path, bytes.buf()).await;
+        if let Err(err) = &res
+            && err.kind() == io::ErrorKind::AlreadyExists
+        {
+            // a journal flush got interrupted after writing the op, it's fine if it's the same op
+            let existing = read_file_optional(&path)
+                .await
+                .map_err(CoreError::storage)?;
+            if existing == Some(bytes.serialize()) {
+                return Ok(());
+            }
+        }
+        res.with_context(|| format!("failed writing ops file {:?}", path))
+            .map_err(CoreError::storage)?;
         Ok(())
     }
 
-    async fn remove_ops(&self, names: Vec<(Uuid, u64)>) -> Result<()> {
-        let futs = names.into_iter().map(|(actor, version)| {
-            let mut path = self.remote_path.join("ops");
-            path.push(actor.to_string());
-            path.push(version.to_string());
-            let path = path;
+    /// Watches the remote dir with the platform's watcher, falling back to polling. If the
+    /// platform's watcher fails, its error is emitted once before polling. If the remote dir
+    /// doesn't exist (e.g. it isn't mounted), the error is emitted and the stream ends. Needs the
+    /// tokio timer to debounce the events.
+    fn watch(&self) -> Option<BoxStream<'static, CoreResult<StorageEvent>>> {
+        let (sender, receiver) = mpsc::unbounded();
+        let watcher = match watch_dir(
+            &self.document_path,
+            &self.remote_path,
+            &self.watch_options,
+            sender,
+        ) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                let err = err.context("failed watching remote dir");
+                return Some(stream::once(async { Err(CoreError::storage(err)) }).boxed());
+            }
+        };
+
+        let document_path = self.document_path.clone();
+        let events = receiver
+            .flat_map(move |res| {
+                let events: Vec<_> = match res {
+                    Ok(event) if event.kind.is_access() || event.kind.is_remove() => Vec::new(),
+                    Ok(event) => event
+                        .paths
+                        .iter()
+                        .filter_map(|path| storage_event(&document_path, path))
+                        .map(Ok)
+                        .collect(),
+                    Err(err) => vec![Err(CoreError::storage(
+                        Error::new(err).context("failed watching remote dir"),
+                    ))],
+                };
+                stream::iter(events)
+            })
+            .boxed();
+
+        Some(
+            debounce(
+                events,
+                watcher,
+                self.watch_options.debounce,
+                self.watch_options.max_delay,
+            )
+            .boxed(),
+        )
+    }
+
+    /// Removes the actor dir as well, once it's empty
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(actors = actor_versions.len()), err)
+    )]
+    async fn remove_ops(&self, actor_versions: Vec<(Uuid, Range<u64>)>) -> CoreResult<()> {
+        let futs = actor_versions.into_iter().map(|(actor, versions)| {
+            let mut actor_dir = self.document_path.join("ops");
+            actor_dir.push(actor.to_string());
+            let actor_dir = actor_dir;
 
             async move {
-                remove_file_optional(&path).await.with_context(|| {
-                    format!(
-                        "failed removing ops file {} for actor {} version {}",
-                        path.display(),
-                        actor,
-                        version
-                    )
-                })
+                read_dir_optional_files(actor_dir.clone())
+                    .try_filter_map(move |entry| {
+                        let in_range = entry
+                            .file_name()
+                            .to_str()
+                            .and_then(|name| u64::from_str(name).ok())
+                            .is_some_and(|version| versions.contains(&version));
+                        async move { Ok(in_range.then(|| entry.path())) }
+                    })
+                    .map_ok(|path| async move {
+                        remove_file_optional(&path)
+                            .await
+                            .with_context(|| format!("failed removing ops file {}", path.display()))
+                    })
+                    .try_buffer_unordered(32)
+                    .try_collect::<()>()
+                    .await
+                    .with_context(|| format!("failed removing ops of actor {}", actor))?;
+
+                // fails if there are ops left, which is fine
+                let _ = fs::remove_dir(&actor_dir).await;
+
+                Result::<_, Error>::Ok(())
             }
         });
 
-        stream::iter(futs).buffer_unordered(32).try_collect().await
+        stream::iter(futs)
+            .buffer_unordered(4)
+            .try_collect()
+            .await
+            .map_err(CoreError::storage)
     }
 }
 
+/// Emits the number and total size of loaded blocks
+#[cfg(feature = "tracing")]
+fn trace_loaded<'a>(blocks: impl Iterator<Item = &'a VersionBytes>) {
+    let (count, bytes) = blocks.fold((0, 0), |(count, bytes), block| {
+        (count + 1, bytes + block.as_ref().len())
+    });
+    tracing::debug!(count, bytes, "loaded");
+}
+
+type WatchSender = mpsc::UnboundedSender<notify::Result<notify::Event>>;
+
+/// Watches `path` recursively. A dir that doesn't exist yet (nothing was written to it) isn't
+/// created, its nearest existing parent within `root` is watched instead. Fails if `root`
+/// doesn't exist either, watching its parents would scan unrelated trees.
+fn watch_dir(
+    path: &Path,
+    root: &Path,
+    options: &WatchOptions,
+    sender: WatchSender,
+) -> Result<Box<dyn Watcher + Send>> {
+    let path = path
+        .ancestors()
+        .take_while(|path| path.starts_with(root))
+        .find(|path| path.is_dir())
+        .with_context(|| format!("remote dir {} doesn't exist", root.display()))?;
+
+    let handler = |sender: WatchSender| {
+        move |res| {
+            // the receiver is gone if the stream was dropped
+            let _ = sender.unbounded_send(res);
+        }
+    };
+
+    let recommended = RecommendedWatcher::new(handler(sender.clone()), notify::Config::default())
+        .and_then(|mut watcher| {
+            watcher.watch(path, RecursiveMode::Recursive)?;
+            Ok(watcher)
+        });
+    let err = match recommended {
+        Ok(watcher) => return Ok(Box::new(watcher)),
+        Err(err) => err,
+    };
+    // polling still works, the caller learns why it's slow
+    let _ = sender.unbounded_send(Err(err));
+
+    let config = notify::Config::default().with_poll_interval(options.poll_interval);
+    let mut watcher =
+        PollWatcher::new(handler(sender), config).context("failed creating poll watcher")?;
+    watcher
+        .watch(path, RecursiveMode::Recursive)
+        .with_context(|| format!("failed polling dir {}", path.display()))?;
+    Ok(Box::new(watcher))
+}
+
+/// Maps a changed path to the event, `None` for paths outside of the known dirs and temporary
+/// files of sync tools (`.syncthing.*.tmp`, `~syncthing~*.tmp`)
+fn storage_event(document_path: &Path, path: &Path) -> Option<StorageEvent> {
+    let path = path.strip_prefix(document_path).ok()?;
+
+    let file_name = path.file_name()?.to_str()?;
+    if file_name.starts_with('.') || file_name.starts_with('~') {
+        return None;
+    }
+
+    let mut components = path.iter();
+    match components.next()?.to_str()? {
+        "meta" => Some(StorageEvent::Meta),
+        "states" => Some(StorageEvent::States),
+        "ops" => {
+            let actor = Uuid::from_str(components.next()?.to_str()?).ok()?;
+            Some(StorageEvent::Ops { actor })
+        }
+        _ => None,
+    }
+}
+
+/// Holds back events until there was no new one for `quiet`, but at most `max_delay` after the
+/// first one, then emits them without duplicates. `watcher` is kept alive as long as the stream.
+fn debounce<W: Send + 'static>(
+    events: BoxStream<'static, CoreResult<StorageEvent>>,
+    watcher: W,
+    quiet: Duration,
+    max_delay: Duration,
+) -> impl Stream<Item = CoreResult<StorageEvent>> + Send + 'static {
+    stream::unfold((events, watcher), move |(mut events, watcher)| async move {
+        let mut batch = vec![events.next().await?];
+        let deadline = time::Instant::now() + max_delay;
+        // an elapsed timeout or the end of the stream finishes the batch
+        loop {
+            let timeout = quiet.min(deadline.saturating_duration_since(time::Instant::now()));
+            match time::timeout(timeout, events.next()).await {
+                Ok(Some(event)) => batch.push(event),
+                _ => break,
+            }
+        }
+
+        let mut deduped: Vec<CoreResult<StorageEvent>> = Vec::with_capacity(batch.len());
+        for event in batch {
+            let is_dup = match &event {
+                Ok(event) => deduped.iter().any(|e| e.as_ref().ok() == Some(event)),
+                Err(_) => false,
+            };
+            if !is_dup {
+                deduped.push(event);
+            }
+        }
+
+        Some((stream::iter(deduped), (events, watcher)))
+    })
+    .flatten()
+}
+
 async fn write_file(path: &Path, buf: impl Buf) -> io::Result<()> {
     write_file_inner(path, buf, false).await
 }
 
+/// Writes a temporary file next to `path` and renames it, so `path` is never left half-written
+async fn write_file_atomic(path: &Path, buf: impl Buf) -> io::Result<()> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    write_file(&tmp_path, buf).await?;
+    fs::rename(&tmp_path, path).await
+}
+
 async fn write_new_file(path: &Path, buf: impl Buf) -> io::Result<()> {
     write_file_inner(path, buf, true).await
 }
@@ -400,10 +1009,8 @@ async fn read_file_optional(path: &Path) -> Result<Option<Vec<u8>>> {
     }
 }
 
-async fn write_content_addressible_file(
-    dir_path: &Path,
-    bytes: &VersionBytesRef<'_>,
-) -> Result<String> {
+/// Base32 encoded SHA3-256 hash of the serialized bytes
+fn content_name(bytes: &VersionBytesRef<'_>) -> String {
     let mut digest = Sha3::v256();
     let mut buf = bytes.buf();
     while buf.has_remaining() {
@@ -414,20 +1021,32 @@ async fn write_content_addressible_file(
     }
     let mut digest_output = [0; 32];
     digest.finalize(&mut digest_output);
-    let block_id = data_encoding::BASE32_NOPAD.encode(&digest_output);
+    data_encoding::BASE32_NOPAD.encode(&digest_output)
+}
+
+async fn write_content_addressible_file(
+    dir_path: &Path,
+    bytes: &VersionBytesRef<'_>,
+) -> Result<String> {
+    let block_id = content_name(bytes);
 
     fs::create_dir_all(dir_path)
         .await
         .with_context(|| format!("failed creating dir {}", dir_path.display()))?;
     let file_path = dir_path.join(&block_id);
-    write_new_file(&file_path, bytes.buf())
-        .await
-        .with_context(|| {
-            format!(
-                "failed writing content addressible file {}",
-                file_path.display()
-            )
-        })?;
+    match write_new_file(&file_path, bytes.buf()).await {
+        Ok(()) => {}
+        // same name, same content
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!(
+                    "failed writing content addressible file {}",
+                    file_path.display()
+                )
+            });
+        }
+    }
     Ok(block_id)
 }
 
@@ -438,3 +1057,51 @@ async fn remove_file_optional(path: &Path) -> Result<()> {
         Err(err) => Err(err).context(format!("failed removing file {}", path.display())),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUIET: Duration = Duration::from_secs(1);
+    const MAX_DELAY: Duration = Duration::from_secs(3);
+
+    #[tokio::test(start_paused = true)]
+    async fn debounce_waits_for_quiet_and_dedups() {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut events = Box::pin(debounce(receiver.boxed(), (), QUIET, MAX_DELAY));
+
+        sender.unbounded_send(Ok(StorageEvent::Meta)).unwrap();
+        sender.unbounded_send(Ok(StorageEvent::States)).unwrap();
+        sender.unbounded_send(Ok(StorageEvent::Meta)).unwrap();
+
+        let start = time::Instant::now();
+        assert_eq!(events.next().await.unwrap().unwrap(), StorageEvent::Meta);
+        assert!(start.elapsed() >= QUIET);
+        assert_eq!(events.next().await.unwrap().unwrap(), StorageEvent::States);
+
+        drop(sender);
+        assert!(events.next().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn debounce_max_delay() {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut events = Box::pin(debounce(receiver.boxed(), (), QUIET, MAX_DELAY));
+
+        // a new event every half `QUIET`, the stream is never quiet
+        tokio::spawn(async move {
+            loop {
+                if sender.unbounded_send(Ok(StorageEvent::Meta)).is_err() {
+                    break;
+                }
+                time::sleep(QUIET / 2).await;
+            }
+        });
+
+        let start = time::Instant::now();
+        assert_eq!(events.next().await.unwrap().unwrap(), StorageEvent::Meta);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= MAX_DELAY, "{elapsed:?}");
+        assert!(elapsed < MAX_DELAY + QUIET, "{elapsed:?}");
+    }
+}
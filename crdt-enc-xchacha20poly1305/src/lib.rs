@@ -2,7 +2,10 @@ use ::agnostik::spawn_blocking;
 use ::anyhow::{Context, Error, Result};
 use ::async_trait::async_trait;
 use ::chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305, XNonce, aead::Aead};
-use ::crdt_enc::utils::{VersionBytes, VersionBytesRef};
+use ::crdt_enc::{
+    Error as CoreError, Result as CoreResult,
+    utils::{VersionBytes, VersionBytesRef},
+};
 use ::rand::{TryRng, rng};
 use ::serde::{Deserialize, Serialize};
 use ::std::{borrow::Cow, fmt::Debug};
@@ -26,22 +29,30 @@ impl EncHandler {
 
 #[async_trait]
 impl crdt_enc::cryptor::Cryptor for EncHandler {
-    async fn gen_key(&self) -> Result<VersionBytes> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, err)
+    )]
+    async fn gen_key(&self) -> CoreResult<VersionBytes> {
         spawn_blocking(|| {
             let mut key = [0u8; KEY_LEN];
             rng()
                 .try_fill_bytes(&mut key)
                 .context("Unable to get random data for secret key")?;
-            Ok(VersionBytes::new(KEY_VERSION, key.into()))
+            Result::<_>::Ok(VersionBytes::new(KEY_VERSION, key.into()))
         })
         .await
+        .map_err(CoreError::encrypt)
     }
 
-    async fn encrypt(&self, key: VersionBytesRef<'_>, clear_text: Vec<u8>) -> Result<Vec<u8>> {
-        key.ensure_version(KEY_VERSION)
-            .context("not matching key version")?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(bytes = clear_text.len()), err)
+    )]
+    async fn encrypt(&self, key: VersionBytesRef<'_>, clear_text: Vec<u8>) -> CoreResult<Vec<u8>> {
+        key.ensure_version(KEY_VERSION)?;
         if key.as_ref().len() != KEY_LEN {
-            return Err(Error::msg("Invalid key length"));
+            return Err(CoreError::encrypt("Invalid key length"));
         }
         let key = key.as_ref().to_vec();
 
@@ -65,16 +76,20 @@ impl crdt_enc::cryptor::Cryptor for EncHandler {
             let version_box = VersionBytesRef::new(DATA_VERSION, enc_box_bytes.as_ref());
             let version_box_bytes =
                 rmp_serde::to_vec_named(&version_box).context("failed to encode version box")?;
-            Ok(version_box_bytes)
+            Result::<_>::Ok(version_box_bytes)
         })
         .await
+        .map_err(CoreError::encrypt)
     }
 
-    async fn decrypt(&self, key: VersionBytesRef<'_>, enc_data: Vec<u8>) -> Result<Vec<u8>> {
-        key.ensure_version(KEY_VERSION)
-            .context("not matching key version")?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(bytes = enc_data.len()), err)
+    )]
+    async fn decrypt(&self, key: VersionBytesRef<'_>, enc_data: Vec<u8>) -> CoreResult<Vec<u8>> {
+        key.ensure_version(KEY_VERSION)?;
         if key.as_ref().len() != KEY_LEN {
-            return Err(Error::msg("Invalid key length"));
+            return Err(CoreError::decrypt("Invalid key length"));
         }
         let key = key.as_ref().to_vec();
 
@@ -95,9 +110,10 @@ impl crdt_enc::cryptor::Cryptor for EncHandler {
             let clear_text = aead
                 .decrypt(&xnonce, enc_box.enc_data.as_ref())
                 .context("Decryption failed")?;
-            Ok(clear_text)
+            Result::<_>::Ok(clear_text)
         })
         .await
+        .map_err(CoreError::decrypt)
     }
 }
 
@@ -0,0 +1,214 @@
+//! Export of the complete remote content of a repository (remote meta, states and ops of every
+//! document) into a single archive, and the import into an empty `Storage`. The blocks are copied
+//! as they are, still encrypted, so no keys are needed for exporting, verifying or importing.
+
+use crate::{
+    Error, Result,
+    storage::{self, Storage},
+    utils::VersionBytes,
+};
+use ::serde::{Deserialize, Serialize};
+use ::tiny_keccak::{Hasher, Sha3};
+use ::uuid::Uuid;
+
+const ARCHIVE_VERSION: Uuid = Uuid::from_u128(0x3f0e5b1c_94a2_4d6e_b1f7_5c2a8e0d9b41);
+
+static SUPPORTED_ARCHIVE_VERSIONS: phf::Set<u128> = phf::phf_set! {
+    0x3f0e5b1c_94a2_4d6e_b1f7_5c2a8e0d9b41_u128,
+};
+
+/// Framed as `VersionBytes` with `ARCHIVE_VERSION`
+#[derive(Serialize, Deserialize)]
+struct ArchiveFile {
+    /// SHA3-256 of `content`
+    #[serde(with = "serde_bytes")]
+    digest: Vec<u8>,
+    /// Serialized `Archive`
+    #[serde(with = "serde_bytes")]
+    content: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    remote_metas: Vec<VersionBytes>,
+    documents: Vec<ArchiveDocument>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveDocument {
+    /// `None` for the default document
+    id: Option<Uuid>,
+    states: Vec<VersionBytes>,
+    ops: Vec<(Uuid, u64, VersionBytes)>,
+}
+
+/// Contents of an archive
+#[derive(Debug, Clone)]
+pub struct ArchiveInfo {
+    remote_metas: usize,
+    documents: usize,
+    states: usize,
+    op_blocks: usize,
+}
+
+impl ArchiveInfo {
+    fn new(archive: &Archive) -> ArchiveInfo {
+        ArchiveInfo {
+            remote_metas: archive.remote_metas.len(),
+            documents: archive.documents.len(),
+            states: archive.documents.iter().map(|doc| doc.states.len()).sum(),
+            op_blocks: archive.documents.iter().map(|doc| doc.ops.len()).sum(),
+        }
+    }
+
+    pub fn remote_metas(&self) -> usize {
+        self.remote_metas
+    }
+
+    /// Number of documents, including the default document
+    pub fn documents(&self) -> usize {
+        self.documents
+    }
+
+    pub fn states(&self) -> usize {
+        self.states
+    }
+
+    pub fn op_blocks(&self) -> usize {
+        self.op_blocks
+    }
+}
+
+/// Exports the remote content of `storage` and all its documents. Use
+/// `VersionBytes::serialize` to write the archive to a file.
+///
+/// Files that are removed while exporting (e.g. by a compaction of another device) are missing
+/// in the archive, so no other device should write in the meantime.
+pub async fn export<ST: Storage>(storage: &ST) -> Result<VersionBytes> {
+    let names = storage.list_remote_meta_names().await?;
+    let remote_metas = storage
+        .load_remote_metas(names)
+        .await?
+        .into_iter()
+        .map(|(_, meta)| meta)
+        .collect();
+
+    let mut documents = vec![export_document(storage, None).await?];
+    for id in storage.list_documents().await? {
+        let document = storage.document(id)?;
+        documents.push(export_document(&document, Some(id)).await?);
+    }
+
+    let content = rmp_serde::to_vec_named(&Archive {
+        remote_metas,
+        documents,
+    })?;
+    let file = ArchiveFile {
+        digest: sha3_256(&content).to_vec(),
+        content,
+    };
+
+    Ok(VersionBytes::new(
+        ARCHIVE_VERSION,
+        rmp_serde::to_vec_named(&file)?,
+    ))
+}
+
+async fn export_document<ST: Storage>(storage: &ST, id: Option<Uuid>) -> Result<ArchiveDocument> {
+    let names = storage.list_state_names().await?;
+    // a corrupt file fails the export, it would be missing in the archive
+    let states = storage
+        .load_states(names)
+        .await?
+        .into_iter()
+        .map(|(_, state)| state)
+        .collect::<Result<_>>()?;
+
+    let mut ops = Vec::new();
+    for actor in storage.list_op_actors().await? {
+        for (actor, version, block) in storage::load_all_ops(storage, actor).await? {
+            ops.push((actor, version, block?));
+        }
+    }
+
+    Ok(ArchiveDocument { id, states, ops })
+}
+
+/// Checks the framing and the checksum of `archive`, no keys are needed.
+pub fn verify(archive: &VersionBytes) -> Result<ArchiveInfo> {
+    let archive = decode(archive)?;
+    Ok(ArchiveInfo::new(&archive))
+}
+
+fn decode(archive: &VersionBytes) -> Result<Archive> {
+    archive.ensure_versions_phf(&SUPPORTED_ARCHIVE_VERSIONS)?;
+
+    let file: ArchiveFile = rmp_serde::from_slice(archive.as_ref())?;
+    if sha3_256(&file.content)[..] != file.digest[..] {
+        return Err(Error::decode("archive checksum mismatch"));
+    }
+
+    Ok(rmp_serde::from_slice(&file.content)?)
+}
+
+/// Verifies `archive` and writes its content into `storage`, which needs to be empty. Works with
+/// any `Storage` implementation, the names of the remote meta and state files are assigned by
+/// the target storage.
+///
+/// Checking that `storage` is empty and writing the content isn't atomic, no device may open or
+/// write to `storage` while importing. Files written in between end up mixed with the imported
+/// ones.
+pub async fn import<ST: Storage>(storage: &ST, archive: &VersionBytes) -> Result<ArchiveInfo> {
+    let archive = decode(archive)?;
+    let info = ArchiveInfo::new(&archive);
+
+    for document in &archive.documents {
+        if let Some(id) = document.id {
+            ensure_empty(&storage.document(id)?).await?;
+        }
+    }
+    ensure_empty(storage).await?;
+    if !storage.list_remote_meta_names().await?.is_empty() {
+        return Err(Error::StorageNotEmpty);
+    }
+
+    // metas first, the states and ops are useless without the keys
+    for meta in archive.remote_metas {
+        storage.store_remote_meta(meta).await?;
+    }
+
+    for document in archive.documents {
+        match document.id {
+            Some(id) => import_document(&storage.document(id)?, document).await?,
+            None => import_document(storage, document).await?,
+        }
+    }
+
+    Ok(info)
+}
+
+async fn ensure_empty<ST: Storage>(storage: &ST) -> Result<()> {
+    if !storage.list_state_names().await?.is_empty() || !storage.list_op_actors().await?.is_empty()
+    {
+        return Err(Error::StorageNotEmpty);
+    }
+    Ok(())
+}
+
+async fn import_document<ST: Storage>(storage: &ST, document: ArchiveDocument) -> Result<()> {
+    for state in document.states {
+        storage.store_state(state).await?;
+    }
+    for (actor, version, ops) in document.ops {
+        storage.store_ops(actor, version, ops).await?;
+    }
+    Ok(())
+}
+
+fn sha3_256(bytes: &[u8]) -> [u8; 32] {
+    let mut digest = Sha3::v256();
+    digest.update(bytes);
+    let mut output = [0; 32];
+    digest.finalize(&mut output);
+    output
+}
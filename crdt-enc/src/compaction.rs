@@ -0,0 +1,145 @@
+use ::std::{fmt, sync::Arc, time::Duration};
+
+/// Decides when `Core` compacts on its own, checked after `Core::apply_ops` and
+/// `Core::read_remote`. Every limit is optional, compaction is triggered as soon as one of the set
+/// limits is reached. The default policy never compacts automatically.
+#[derive(Clone, Default)]
+pub struct CompactionPolicy {
+    /// Number of op files not yet covered by a state
+    pub max_op_files: Option<usize>,
+    /// Total size of the op files not yet covered by a state
+    pub max_op_bytes: Option<u64>,
+    /// Number of state files, see `CompactionStats::state_files`
+    pub max_state_files: Option<usize>,
+    /// Time since the last compaction (or since opening the repository)
+    pub max_interval: Option<Duration>,
+    /// Called before an automatic compaction, returning `true` skips it, e.g. on metered
+    /// connections. It's checked again on the next trigger.
+    pub veto: Option<Arc<dyn Fn(&CompactionStats) -> bool + Send + Sync>>,
+}
+
+impl CompactionPolicy {
+    pub(crate) fn should_compact(&self, stats: &CompactionStats) -> bool {
+        if stats.op_files == 0 && stats.state_files <= 1 {
+            // nothing to compact
+            return false;
+        }
+
+        let triggered = self.max_op_files.is_some_and(|max| max <= stats.op_files)
+            || self.max_op_bytes.is_some_and(|max| max <= stats.op_bytes)
+            || self
+                .max_state_files
+                .is_some_and(|max| max <= stats.state_files)
+            || self
+                .max_interval
+                .is_some_and(|max| max <= stats.since_last_compaction);
+
+        triggered && !self.veto.as_ref().is_some_and(|veto| veto(stats))
+    }
+}
+
+impl fmt::Debug for CompactionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompactionPolicy")
+            .field("max_op_files", &self.max_op_files)
+            .field("max_op_bytes", &self.max_op_bytes)
+            .field("max_state_files", &self.max_state_files)
+            .field("max_interval", &self.max_interval)
+            .field("veto", &self.veto.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompactionStats {
+    pub op_files: usize,
+    pub op_bytes: u64,
+    /// State files not superseded by another state. Superseded ones are kept until every actor
+    /// has read them, see `Core::compact`.
+    pub state_files: usize,
+    pub since_last_compaction: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> CompactionStats {
+        CompactionStats {
+            op_files: 10,
+            op_bytes: 1000,
+            state_files: 2,
+            since_last_compaction: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn default_never_compacts() {
+        assert!(!CompactionPolicy::default().should_compact(&stats()));
+    }
+
+    #[test]
+    fn nothing_to_compact() {
+        let policy = CompactionPolicy {
+            max_op_files: Some(0),
+            max_interval: Some(Duration::ZERO),
+            ..CompactionPolicy::default()
+        };
+        let stats = CompactionStats {
+            op_files: 0,
+            state_files: 1,
+            ..stats()
+        };
+        assert!(!policy.should_compact(&stats));
+    }
+
+    #[test]
+    fn thresholds() {
+        let policies = [
+            CompactionPolicy {
+                max_op_files: Some(10),
+                ..CompactionPolicy::default()
+            },
+            CompactionPolicy {
+                max_op_bytes: Some(1000),
+                ..CompactionPolicy::default()
+            },
+            CompactionPolicy {
+                max_state_files: Some(2),
+                ..CompactionPolicy::default()
+            },
+            CompactionPolicy {
+                max_interval: Some(Duration::from_secs(60)),
+                ..CompactionPolicy::default()
+            },
+        ];
+
+        for policy in policies {
+            assert!(policy.should_compact(&stats()), "{policy:?}");
+
+            let below = CompactionStats {
+                op_files: 9,
+                op_bytes: 999,
+                state_files: 1,
+                since_last_compaction: Duration::from_secs(59),
+            };
+            assert!(!policy.should_compact(&below), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn veto() {
+        let policy = CompactionPolicy {
+            max_op_files: Some(1),
+            veto: Some(Arc::new(|stats| stats.op_bytes > 100)),
+            ..CompactionPolicy::default()
+        };
+        assert!(!policy.should_compact(&stats()));
+
+        let stats = CompactionStats {
+            op_bytes: 100,
+            ..stats()
+        };
+        assert!(policy.should_compact(&stats));
+    }
+}
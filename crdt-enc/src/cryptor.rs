@@ -1,8 +1,7 @@
 use crate::{
-    CoreSubHandle,
+    CoreSubHandle, Result,
     utils::{VersionBytes, VersionBytesRef},
 };
-use ::anyhow::Result;
 use ::async_trait::async_trait;
 use ::crdts::MVReg;
 use ::std::fmt::Debug;
@@ -21,6 +20,11 @@ where
         Ok(())
     }
 
+    /// Called by `Core::close`, after the last write
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
     async fn gen_key(&self) -> Result<VersionBytes>;
     async fn encrypt(&self, key: VersionBytesRef<'_>, clear_text: Vec<u8>) -> Result<Vec<u8>>;
     async fn decrypt(&self, key: VersionBytesRef<'_>, enc_data: Vec<u8>) -> Result<Vec<u8>>;
@@ -0,0 +1,79 @@
+use crate::{Error, Result, quarantine::BlockId};
+use ::crdts::VClock;
+use ::serde::Serialize;
+use ::std::error::Error as StdError;
+use ::uuid::Uuid;
+
+/// Clear text of the remote files of a document, returned by `Core::debug_dump`
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugDump<S, O> {
+    /// The local actor
+    pub actor: Uuid,
+    /// The merged local state
+    pub state: S,
+    /// The op clock of the merged local state
+    pub clock: VClock<Uuid>,
+    /// Every readable state file, ordered by name
+    pub states: Vec<DebugState<S>>,
+    /// Every readable op file, ordered by version per actor
+    pub op_blocks: Vec<DebugOpBlock<O>>,
+    /// Files that could not be loaded, decrypted or decoded
+    pub errors: Vec<DebugError>,
+}
+
+impl<S, O> DebugDump<S, O>
+where
+    S: Serialize,
+    O: Serialize,
+{
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(Error::encode)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugState<S> {
+    pub name: String,
+    pub key_id: Uuid,
+    pub data_version: Uuid,
+    /// Op versions contained in the state
+    pub clock: VClock<Uuid>,
+    pub state: S,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugOpBlock<O> {
+    pub actor: Uuid,
+    pub version: u64,
+    pub key_id: Uuid,
+    pub data_version: Uuid,
+    pub ops: Vec<O>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugError {
+    /// `None` if the op files of an actor couldn't be listed or loaded, the error names the actor
+    pub block: Option<BlockId>,
+    /// The error and its sources, separated by `: `
+    pub error: String,
+}
+
+impl DebugError {
+    pub(crate) fn for_actor(actor: Uuid, err: &Error) -> DebugError {
+        let mut debug_error = DebugError::new(None, err);
+        debug_error.error = format!("op files of actor {}: {}", actor, debug_error.error);
+        debug_error
+    }
+
+    pub(crate) fn new(block: Option<BlockId>, err: &Error) -> DebugError {
+        let mut error = err.to_string();
+        let mut source = err.source();
+        while let Some(err) = source {
+            error.push_str(": ");
+            error.push_str(&err.to_string());
+            source = err.source();
+        }
+
+        DebugError { block, error }
+    }
+}
@@ -0,0 +1,20 @@
+use crate::Result;
+use ::crdts::VClock;
+use ::futures::future::BoxFuture;
+use ::std::{any::Any, collections::HashSet, sync::Arc};
+use ::uuid::Uuid;
+
+/// Type erased view of an open document (`Core`), used for the parts of the repository that are
+/// shared across documents with different state types.
+pub(crate) trait OpenDocument: Send + Sync {
+    /// Ids of the keys referenced by the states and op blocks known to the document
+    fn used_key_ids(&self) -> HashSet<Uuid>;
+
+    /// Op clock of the merged state of the document
+    fn op_clock(&self) -> VClock<Uuid>;
+
+    /// `Core::close`, does nothing if the document is closed already
+    fn close_document(self: Arc<Self>, compact: bool) -> BoxFuture<'static, Result<()>>;
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+}
@@ -0,0 +1,184 @@
+use crate::{
+    key_cryptor::UnknownKeyError,
+    utils::{DeserializeError, VersionError},
+    verify::RemoteFile,
+};
+use ::std::error::Error as StdError;
+use ::uuid::Uuid;
+
+pub type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+pub type Result<T, E = Error> = ::std::result::Result<T, E>;
+
+/// Error type of the public `crdt-enc` API. Backends (`Storage`, `Cryptor`, `KeyCryptor`) wrap
+/// their own errors into the matching variant, e.g. `Error::storage(err)`.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The data was written with a version that is not supported
+    #[error(transparent)]
+    Version(#[from] VersionError),
+
+    #[error("local meta does not exist, and `create` option is not set")]
+    LocalMetaMissing,
+
+    /// No data key is available, yet
+    #[error("no data key available")]
+    NoKey,
+
+    /// A block references a key that is not (or no longer) known
+    #[error(transparent)]
+    UnknownKey(#[from] UnknownKeyError),
+
+    #[error("unexpected op version {got} of actor {actor}, expected {expected}")]
+    UnexpectedOpVersion {
+        actor: Uuid,
+        expected: u64,
+        got: u64,
+    },
+
+    /// The document is already open with a different state type
+    #[error("document {id} is already open with a different state type")]
+    DocumentTypeMismatch { id: Uuid },
+
+    /// The actor was retired (`Core::retire_actor`) and can't write ops anymore
+    #[error("actor {actor} is retired")]
+    RetiredActor { actor: Uuid },
+
+    /// `Core::retire_actor` was called with the actor of this device
+    #[error("the local actor can't be retired")]
+    RetireLocalActor,
+
+    /// `Core::close` was called
+    #[error("core is closed")]
+    Closed,
+
+    /// The core was opened with `OpenOptions::read_only`
+    #[error("core is read only")]
+    ReadOnly,
+
+    /// Compacting would lose the op blocks skipped with `Core::remove_quarantined`, see
+    /// `Core::skipped_ops`
+    #[error("op blocks are skipped, compacting would lose them")]
+    SkippedOps,
+
+    /// The signature of a block is missing or doesn't match the actor it claims to be written
+    /// by
+    #[error("invalid or missing block signature")]
+    InvalidSignature,
+
+    /// A block is signed by an actor whose signing key isn't known (synced), yet. Unsigned op
+    /// blocks written after the first signing key was published fail the same way, until the
+    /// key of their actor is known. Devices that don't sign their blocks need to be upgraded.
+    #[error("signing key of actor {actor} is not known")]
+    UnknownSigner { actor: Uuid },
+
+    /// The remote presents fewer op blocks of `actor` than this device has seen before, states
+    /// or ops were rolled back or removed. See `OpenOptions::accept_rollback`.
+    #[error("remote rolled back: {presented} op blocks of actor {actor}, {seen} seen before")]
+    RolledBack {
+        actor: Uuid,
+        seen: u64,
+        presented: u64,
+    },
+
+    /// `archive::import` needs an empty storage
+    #[error("storage is not empty")]
+    StorageNotEmpty,
+
+    /// The storage doesn't implement an optional method, see `Storage`
+    #[error("{0} is not supported by this storage")]
+    Unsupported(&'static str),
+
+    /// The storage backend failed, retrying later might help
+    #[error("storage failed")]
+    Storage(#[source] BoxError),
+
+    #[error("encryption failed")]
+    Encrypt(#[source] BoxError),
+
+    /// Decrypting failed, most likely because of a wrong key or corrupted data. The source is a
+    /// `FileError` if the data was read from a remote file.
+    #[error("decryption failed")]
+    Decrypt(#[source] BoxError),
+
+    #[error("key cryptor failed")]
+    KeyCryptor(#[source] BoxError),
+
+    #[error("failed encoding data")]
+    Encode(#[source] BoxError),
+
+    /// The data is corrupted or not in the expected format. The source is a `FileError` if the
+    /// data was read from a remote file.
+    #[error("failed decoding data")]
+    Decode(#[source] BoxError),
+}
+
+impl Error {
+    pub fn storage(err: impl Into<BoxError>) -> Error {
+        Error::Storage(err.into())
+    }
+
+    pub fn encrypt(err: impl Into<BoxError>) -> Error {
+        Error::Encrypt(err.into())
+    }
+
+    pub fn decrypt(err: impl Into<BoxError>) -> Error {
+        Error::Decrypt(err.into())
+    }
+
+    pub fn key_cryptor(err: impl Into<BoxError>) -> Error {
+        Error::KeyCryptor(err.into())
+    }
+
+    pub fn encode(err: impl Into<BoxError>) -> Error {
+        Error::Encode(err.into())
+    }
+
+    pub fn decode(err: impl Into<BoxError>) -> Error {
+        Error::Decode(err.into())
+    }
+
+    /// Names the remote file a `Decrypt` or `Decode` error occurred in, other errors are
+    /// returned as they are
+    pub(crate) fn in_file(self, file: RemoteFile) -> Error {
+        match self {
+            Error::Decrypt(source) => Error::Decrypt(Box::new(FileError { file, source })),
+            Error::Decode(source) => Error::Decode(Box::new(FileError { file, source })),
+            err => err,
+        }
+    }
+}
+
+/// Source of a `Decrypt` or `Decode` error, names the remote file that failed
+#[derive(Debug, thiserror::Error)]
+#[error("{file}")]
+pub struct FileError {
+    file: RemoteFile,
+    #[source]
+    source: BoxError,
+}
+
+impl FileError {
+    pub fn file(&self) -> &RemoteFile {
+        &self.file
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(err: rmp_serde::encode::Error) -> Error {
+        Error::encode(err)
+    }
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(err: rmp_serde::decode::Error) -> Error {
+        Error::decode(err)
+    }
+}
+
+impl From<DeserializeError> for Error {
+    fn from(err: DeserializeError) -> Error {
+        Error::decode(err)
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{quarantine::BlockId, utils::LockBox};
+use ::crdts::VClock;
+use ::futures::channel::mpsc;
+use ::uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Remote states and/or ops were merged by `Core::read_remote`
+    Remote,
+    /// Local ops were applied by `Core::apply_ops` or `Core::update`
+    Local,
+    /// A new full state was written by `Core::compact`
+    Compaction,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangeEvent {
+    pub(crate) kind: ChangeKind,
+    pub(crate) states: Vec<String>,
+    pub(crate) ops: Vec<(Uuid, u64)>,
+    pub(crate) quarantined: Vec<BlockId>,
+    pub(crate) clock: VClock<Uuid>,
+}
+
+impl ChangeEvent {
+    pub fn kind(&self) -> ChangeKind {
+        self.kind
+    }
+
+    /// Names of the states that were merged, or for `ChangeKind::Compaction` the name of the newly
+    /// written state.
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+
+    /// Whether at least one full state was merged.
+    pub fn state_merged(&self) -> bool {
+        self.kind == ChangeKind::Remote && !self.states.is_empty()
+    }
+
+    /// `(actor, version)` of every applied op block, in the order they were applied.
+    pub fn ops(&self) -> &[(Uuid, u64)] {
+        &self.ops
+    }
+
+    /// Remote blocks that were quarantined while reading, see `Core::quarantined`.
+    pub fn quarantined(&self) -> &[BlockId] {
+        &self.quarantined
+    }
+
+    /// The op clock after the change was applied.
+    pub fn clock(&self) -> &VClock<Uuid> {
+        &self.clock
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Subscribers {
+    senders: LockBox<Vec<mpsc::UnboundedSender<ChangeEvent>>>,
+}
+
+impl Subscribers {
+    pub(crate) fn new() -> Subscribers {
+        Subscribers {
+            senders: LockBox::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> mpsc::UnboundedReceiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.senders.with(|senders| senders.push(sender));
+        receiver
+    }
+
+    /// Sends the event to all subscribers, drops the ones that went away.
+    pub(crate) fn notify(&self, event: ChangeEvent) {
+        self.senders.with(|senders| {
+            senders.retain(|sender| sender.unbounded_send(event.clone()).is_ok());
+        });
+    }
+}
@@ -0,0 +1,33 @@
+use ::std::ops::Range;
+use ::uuid::Uuid;
+
+/// Remote files removed by `Core::gc`, or for a dry run the ones that would be removed
+#[derive(Debug, Clone)]
+pub struct GcReport {
+    pub(crate) dry_run: bool,
+    pub(crate) states: Vec<String>,
+    pub(crate) ops: Vec<(Uuid, Range<u64>)>,
+    pub(crate) remote_metas: Vec<String>,
+}
+
+impl GcReport {
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Names of the states that are contained in another state
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+
+    /// Op versions per actor that are contained in every remaining state. Not every version in
+    /// the range needs to exist, earlier compactions might have removed them already.
+    pub fn ops(&self) -> &[(Uuid, Range<u64>)] {
+        &self.ops
+    }
+
+    /// Names of the remote meta files that are replaced by a single merged one
+    pub fn remote_metas(&self) -> &[String] {
+        &self.remote_metas
+    }
+}
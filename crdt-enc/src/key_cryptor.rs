@@ -1,16 +1,16 @@
 use crate::{
-    CoreSubHandle,
+    CoreSubHandle, Result,
+    signing::ActorKey,
     utils::{VersionBytes, VersionBytesRef},
 };
-use ::anyhow::Result;
 use ::async_trait::async_trait;
-use ::crdts::{CmRDT, CvRDT, MVReg, Orswot, ctx::ReadCtx};
+use ::crdts::{CmRDT, CvRDT, GSet, MVReg, Orswot, ctx::ReadCtx};
 use ::serde::{Deserialize, Serialize};
 use ::std::{
     borrow::Borrow,
     cmp::{Eq, Ord, Ordering, PartialEq},
     convert::Infallible,
-    fmt::Debug,
+    fmt::{self, Debug},
     hash::{Hash, Hasher},
 };
 use ::uuid::Uuid;
@@ -28,6 +28,11 @@ where
         Ok(())
     }
 
+    /// Called by `Core::close`, after the last write
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// It needs to give a new `ReadCtx<Keys>` to the core (`core.set_keys`)
     async fn set_keys(&self, keys: ReadCtx<Keys, Uuid>) -> Result<()>;
 }
@@ -36,6 +41,9 @@ where
 pub struct Keys {
     latest_key_id: MVReg<Uuid, Uuid>,
     keys: Orswot<Key, Uuid>,
+    /// Signing keys published by the actors, only members can publish them
+    #[serde(default)]
+    actor_keys: GSet<ActorKey>,
 }
 
 impl CvRDT for Keys {
@@ -48,6 +56,7 @@ impl CvRDT for Keys {
     fn merge(&mut self, other: Keys) {
         self.latest_key_id.merge(other.latest_key_id);
         self.keys.merge(other.keys);
+        self.actor_keys.merge(other.actor_keys);
     }
 }
 
@@ -56,17 +65,50 @@ impl Keys {
         self.keys.read().val.take(&key_id)
     }
 
-    pub fn latest_key(&self) -> Option<Key> {
+    pub fn try_get_key(&self, key_id: Uuid) -> Result<Key, UnknownKeyError> {
+        self.get_key(key_id).ok_or(UnknownKeyError { key_id })
+    }
+
+    /// Returns `Ok(None)` if no key was inserted yet.
+    pub fn latest_key(&self) -> Result<Option<Key>, UnknownKeyError> {
         let mut keys = self.keys.read().val;
         self.latest_key_id
             .read()
             .val
             .into_iter()
-            .map(move |id| {
-                keys.take(&id)
-                    .unwrap_or_else(|| panic!("Could not find key for latest key id {}", id))
+            .map(move |key_id| keys.take(&key_id).ok_or(UnknownKeyError { key_id }))
+            .try_fold(None, |min: Option<Key>, key| {
+                let key = key?;
+                Ok(Some(match min {
+                    Some(min) if min <= key => min,
+                    _ => key,
+                }))
             })
-            .min()
+    }
+
+    pub fn latest_key_ids(&self) -> Vec<Uuid> {
+        self.latest_key_id.read().val
+    }
+
+    pub fn key_ids(&self) -> Vec<Uuid> {
+        self.keys.read().val.iter().map(Key::id).collect()
+    }
+
+    /// Removes the keys with the given ids, unknown ids are ignored.
+    pub fn remove_keys(&mut self, key_ids: &[Uuid]) {
+        let keys: Vec<_> = self
+            .keys
+            .read()
+            .val
+            .into_iter()
+            .filter(|key| key_ids.contains(&key.id()))
+            .collect();
+
+        for key in keys {
+            let rm_ctx = self.keys.read_ctx().derive_rm_ctx();
+            let op = self.keys.rm(key, rm_ctx);
+            self.keys.apply(op);
+        }
     }
 
     pub fn insert_latest_key(&mut self, actor: Uuid, new_key: Key) {
@@ -80,8 +122,36 @@ impl Keys {
         let op = self.latest_key_id.write(key_id, write_ctx);
         self.latest_key_id.apply(op);
     }
+
+    /// Signing keys published by the actors of all documents
+    pub(crate) fn actor_keys(&self) -> Vec<ActorKey> {
+        self.actor_keys.read().into_iter().collect()
+    }
+
+    pub(crate) fn insert_actor_key(&mut self, actor_key: ActorKey) {
+        self.actor_keys.insert(actor_key);
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownKeyError {
+    key_id: Uuid,
+}
+
+impl UnknownKeyError {
+    pub fn key_id(&self) -> Uuid {
+        self.key_id
+    }
 }
 
+impl fmt::Display for UnknownKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown key id {}", self.key_id)
+    }
+}
+
+impl std::error::Error for UnknownKeyError {}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Key {
     id: Uuid,
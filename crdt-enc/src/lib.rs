@@ -1,28 +1,67 @@
+pub mod archive;
+pub mod compaction;
 pub mod cryptor;
+pub mod debug;
+mod document;
+pub mod error;
+pub mod event;
+pub mod gc;
 pub mod key_cryptor;
+pub mod migration;
+pub mod quarantine;
+mod signing;
 pub mod storage;
+pub mod sync;
 pub mod utils;
+pub mod verify;
+
+pub use crate::error::{BoxError, Error, Result};
 
 use crate::{
+    compaction::{CompactionPolicy, CompactionStats},
     cryptor::Cryptor,
+    debug::{DebugDump, DebugError, DebugOpBlock, DebugState},
+    document::OpenDocument,
+    event::{ChangeEvent, ChangeKind, Subscribers},
+    gc::GcReport,
     key_cryptor::{Key, KeyCryptor, Keys},
-    storage::Storage,
+    migration::Migrations,
+    quarantine::{BlockId, QuarantinedBlock},
+    signing::{ActorKey, ActorKeys, BlockSignature, BlockTarget},
+    storage::{Storage, StorageEvent},
+    sync::{SyncOptions, SyncState, SyncTrigger},
     utils::{LockBox, VersionBytes, VersionBytesRef},
+    verify::{Issue, RemoteFile, VerifyReport},
 };
-use ::anyhow::{Context, Error, Result};
 use ::async_trait::async_trait;
-use ::crdts::{CmRDT, CvRDT, MVReg, VClock, ctx::ReadCtx};
+use ::crdts::{CmRDT, CvRDT, Dot, GSet, MVReg, VClock, ctx::ReadCtx};
 use ::dyn_clone::DynClone;
+use ::ed25519_dalek::SigningKey;
 use ::futures::{
+    future::BoxFuture,
     lock::Mutex as AsyncMutex,
-    stream::{self, StreamExt, TryStreamExt},
+    stream::{self, Stream, StreamExt, TryStreamExt},
 };
 use ::serde::{Deserialize, Serialize, de::DeserializeOwned};
 use ::std::{
-    collections::HashSet, convert::Infallible, default::Default, fmt::Debug, mem, sync::Arc,
+    any::Any,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    default::Default,
+    fmt::Debug,
+    mem,
+    sync::{
+        Arc, Weak,
+        atomic::{self, AtomicBool},
+    },
+    time::Instant,
 };
 use ::uuid::Uuid;
 
+/// See `Core::take_background_errors`
+const MAX_BACKGROUND_ERRORS: usize = 32;
+
 const CURRENT_VERSION: Uuid = Uuid::from_u128(0xe834d789_101b_4634_9823_9de990a9051f);
 
 static SUPPORTED_VERSIONS: phf::Set<u128> = phf::phf_set! {
@@ -30,6 +69,15 @@ static SUPPORTED_VERSIONS: phf::Set<u128> = phf::phf_set! {
     0x_e834d789_101b_4634_9823_9de990a9051f_u128,
 };
 
+const BLOCK_VERSION: Uuid = Uuid::from_u128(0x7c9caf5a_6b70_406e_8ce1_b445b0b40293);
+
+static SUPPORTED_BLOCK_VERSIONS: phf::Set<u128> = phf::phf_set! {
+    // current
+    0x_7c9caf5a_6b70_406e_8ce1_b445b0b40293_u128,
+    // legacy: raw encrypted data without key id, always encrypted with the latest key
+    0x_e834d789_101b_4634_9823_9de990a9051f_u128,
+};
+
 #[async_trait]
 pub trait CoreSubHandle
 where
@@ -186,24 +234,134 @@ where
 // }
 
 #[derive(Debug)]
-pub struct Core<S, ST, C, KC> {
+pub struct Core<S, ST, C, KC>
+where
+    S: CmRDT,
+{
+    /// `None` for the default document of the repository
+    document: Option<Uuid>,
     storage: ST,
-    cryptor: C,
-    key_cryptor: KC,
+    cryptor: Arc<C>,
+    key_cryptor: Arc<KC>,
+    shared: Arc<LockBox<SharedData>>,
     data: LockBox<CoreMutData<S>>,
     supported_data_versions: Vec<Uuid>,
     current_data_version: Uuid,
+    migrations: Migrations<S>,
     apply_ops_lock: AsyncMutex<()>,
+    journal_lock: AsyncMutex<()>,
+    compact_lock: AsyncMutex<()>,
+    key_pins_lock: AsyncMutex<()>,
+    compaction_policy: CompactionPolicy,
+    subscribers: Subscribers,
+    /// See `take_background_errors`
+    background_errors: LockBox<VecDeque<Error>>,
+    closed: AtomicBool,
+    signing_key_published: AtomicBool,
+    /// Whether `check_high_water` passed once, `accept_rollback` only applies to the first check
+    high_water_checked: AtomicBool,
+    read_only: bool,
+    accept_rollback: bool,
 }
 
+/// Data shared by all documents of a repository
 #[derive(Debug)]
-struct CoreMutData<S> {
+struct SharedData {
     local_meta: Option<LocalMeta>,
+    signing_key: Option<SigningKey>,
     remote_meta: RemoteMeta,
     keys: Option<ReadCtx<Keys, Uuid>>,
+    read_remote_metas: HashSet<String>,
+    /// Open documents, `None` is the default document
+    documents: HashMap<Option<Uuid>, Weak<dyn OpenDocument>>,
+}
+
+impl SharedData {
+    fn keys(&self) -> Result<&Keys> {
+        let keys = self.keys.as_ref().ok_or(Error::NoKey)?;
+        Ok(&keys.val)
+    }
+
+    fn latest_key(&self) -> Result<Key> {
+        self.keys()?.latest_key()?.ok_or(Error::NoKey)
+    }
+
+    fn local_actor(&self) -> Result<Uuid> {
+        let local_meta = self.local_meta.as_ref().ok_or(Error::LocalMetaMissing)?;
+        Ok(local_meta.local_actor_id)
+    }
+
+    fn signing_key(&self) -> Result<&SigningKey> {
+        self.signing_key.as_ref().ok_or(Error::LocalMetaMissing)
+    }
+}
+
+#[derive(Debug)]
+struct CoreMutData<S> {
     state: StateWrapper<S>,
     read_states: HashSet<String>,
-    read_remote_metas: HashSet<String>,
+    /// Infos about the read states, used to find keys and states that are no longer needed
+    state_infos: HashMap<String, StateInfo>,
+    /// Op blocks that are not yet covered by a compacted state
+    op_blocks: HashMap<(Uuid, u64), OpBlockInfo>,
+    quarantine: HashMap<BlockId, QuarantinedBlock>,
+    last_compaction: Instant,
+    /// Op blocks of the local actor by version, that are applied but not yet written to the
+    /// remote
+    journal: BTreeMap<u64, VersionBytes>,
+    /// `(actor, version)` of the op blocks given up on with `Core::remove_quarantined`. The
+    /// op clock (`next_op_versions`) moved past them, but the state doesn't contain them.
+    skipped_ops: BTreeSet<(Uuid, u64)>,
+    /// Public signing key of each actor of the document, pinned when it was first seen, see
+    /// `ActorKeys`
+    signing_key_pins: HashMap<Uuid, Vec<u8>>,
+    /// Whether `signing_key_pins` changed since they were stored, see `Core::store_key_pins`
+    signing_key_pins_changed: bool,
+    /// Op clock of everything this device has seen of the document in the remote, see
+    /// `Core::check_high_water`
+    high_water: VClock<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StateInfo {
+    key_id: Uuid,
+    /// Ops contained in the state
+    clock: VClock<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpBlockInfo {
+    key_id: Uuid,
+    size: u64,
+}
+
+impl<S> CoreMutData<S> {
+    fn quarantine_block(&mut self, id: BlockId, cause: Error) {
+        let block = QuarantinedBlock {
+            id: id.clone(),
+            cause: Arc::new(cause),
+        };
+        self.quarantine.insert(id, block);
+    }
+
+    /// The op clock up to the first skipped op of every actor, the ops the state contains
+    /// without a gap. Recorded as read clock, so the skipped op files are kept for the devices
+    /// that can read them.
+    fn read_clock(&self) -> VClock<Uuid> {
+        let mut clock = VClock::new();
+        for dot in self.state.next_op_versions.iter() {
+            let actor = *dot.actor;
+            let counter = self
+                .skipped_ops
+                .range((actor, 0)..=(actor, u64::MAX))
+                .next()
+                .map_or(dot.counter, |&(_, version)| version.min(dot.counter));
+            if counter > 0 {
+                clock.apply(Dot::new(actor, counter));
+            }
+        }
+        clock
+    }
 }
 
 impl<S, ST, C, KC> Core<S, ST, C, KC>
@@ -223,64 +381,69 @@ where
     C: Cryptor,
     KC: KeyCryptor,
 {
-    pub async fn open(options: OpenOptions<ST, C, KC>) -> Result<Arc<Self>> {
-        let mut supported_data_versions = options.supported_data_versions;
-        supported_data_versions.sort_unstable();
-
-        let core = Arc::new(Core {
-            storage: options.storage,
-            cryptor: options.cryptor,
-            key_cryptor: options.key_cryptor,
-            supported_data_versions,
-            current_data_version: options.current_data_version,
-            data: LockBox::new(CoreMutData {
-                local_meta: None,
-                remote_meta: RemoteMeta::default(),
-                keys: None,
-                state: StateWrapper {
-                    next_op_versions: Default::default(),
-                    state: Default::default(),
-                },
-                read_states: HashSet::new(),
-                read_remote_metas: HashSet::new(),
-            }),
-            apply_ops_lock: AsyncMutex::new(()),
-        });
+    pub async fn open(options: OpenOptions<S, ST, C, KC>) -> Result<Arc<Self>> {
+        let shared = SharedData {
+            local_meta: None,
+            signing_key: None,
+            remote_meta: RemoteMeta::default(),
+            keys: None,
+            read_remote_metas: HashSet::new(),
+            documents: HashMap::new(),
+        };
 
-        let local_meta = core
-            .storage
-            .load_local_meta()
-            .await
-            .context("failed getting local meta")?;
-        let local_meta: LocalMeta = match local_meta {
+        let core = Arc::new(Core::new(
+            None,
+            options.storage,
+            Arc::new(options.cryptor),
+            Arc::new(options.key_cryptor),
+            Arc::new(LockBox::new(shared)),
+            options.supported_data_versions,
+            options.current_data_version,
+            options.migrations,
+            options.compaction_policy,
+            options.read_only,
+            options.accept_rollback,
+        ));
+
+        let local_meta = core.storage.load_local_meta().await?;
+        let (mut local_meta, mut store_local_meta): (LocalMeta, _) = match local_meta {
             Some(local_meta) => {
                 local_meta.ensure_versions_phf(&SUPPORTED_VERSIONS)?;
-                rmp_serde::from_slice(local_meta.as_ref())?
+                (rmp_serde::from_slice(local_meta.as_ref())?, false)
             }
             None => {
                 if !options.create {
-                    return Err(Error::msg(
-                        "local meta does not exist, and `create` option is not set",
-                    ));
+                    return Err(Error::LocalMetaMissing);
                 }
                 let local_meta = LocalMeta {
                     local_actor_id: Uuid::new_v4(),
+                    signing_key: None,
                 };
-                let vbox =
-                    VersionBytes::new(CURRENT_VERSION, rmp_serde::to_vec_named(&local_meta)?);
-
-                core.storage
-                    .store_local_meta(vbox)
-                    .await
-                    .context("failed storing local meta")?;
-                local_meta
+                (local_meta, true)
             }
         };
 
-        let actor = local_meta.local_actor_id;
+        // local metas written before blocks were signed don't have a key, yet
+        if local_meta.signing_key.is_none() {
+            local_meta.signing_key = Some(signing::gen_signing_key()?);
+            store_local_meta = true;
+        }
 
-        core.data.with(|data| {
-            data.local_meta = Some(local_meta);
+        // a read only core never writes ops, the actor doesn't need to be persisted
+        if store_local_meta && !options.read_only {
+            let vbox = VersionBytes::new(CURRENT_VERSION, rmp_serde::to_vec_named(&local_meta)?);
+            core.storage.store_local_meta(vbox).await?;
+        }
+
+        let actor = local_meta.local_actor_id;
+        let signing_key =
+            signing::signing_key_from_bytes(local_meta.signing_key.as_deref().unwrap_or_default())?;
+
+        core.shared.with(|shared| {
+            shared.local_meta = Some(local_meta);
+            shared.signing_key = Some(signing_key);
+            let document: Weak<dyn OpenDocument> = Arc::downgrade(&core);
+            shared.documents.insert(None, document);
         });
 
         futures::try_join![
@@ -292,13 +455,17 @@ where
         core.read_remote_meta_(true).await?;
 
         let insert_new_key = core
-            .data
-            .with(|data| data.keys.as_ref().unwrap().val.latest_key().is_none());
+            .shared
+            .try_with(|shared| Ok(shared.keys()?.latest_key()?.is_none()))?;
         if insert_new_key {
+            if core.read_only {
+                return Err(Error::NoKey);
+            }
+
             let new_key = core.cryptor.gen_key().await?;
 
-            let keys_ctx = core.data.with(|data| {
-                let mut keys_ctx = data.keys.take().unwrap();
+            let keys_ctx = core.shared.with(|shared| {
+                let mut keys_ctx = shared.keys.take().unwrap();
                 keys_ctx.val.insert_latest_key(actor, Key::new(new_key));
                 keys_ctx
             });
@@ -307,12 +474,169 @@ where
             core.key_cryptor.set_keys(keys_ctx).await?;
         }
 
+        core.load_cache().await?;
+        core.load_journal().await?;
+        core.load_high_water().await?;
+        core.load_key_pins().await?;
+        core.try_publish_signing_key().await;
+
         Ok(core)
     }
 
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        document: Option<Uuid>,
+        storage: ST,
+        cryptor: Arc<C>,
+        key_cryptor: Arc<KC>,
+        shared: Arc<LockBox<SharedData>>,
+        mut supported_data_versions: Vec<Uuid>,
+        current_data_version: Uuid,
+        migrations: Migrations<S>,
+        compaction_policy: CompactionPolicy,
+        read_only: bool,
+        accept_rollback: bool,
+    ) -> Self {
+        supported_data_versions.extend(migrations.versions());
+        supported_data_versions.sort_unstable();
+        supported_data_versions.dedup();
+
+        Core {
+            document,
+            storage,
+            cryptor,
+            key_cryptor,
+            shared,
+            supported_data_versions,
+            current_data_version,
+            migrations,
+            data: LockBox::new(CoreMutData {
+                state: StateWrapper {
+                    next_op_versions: Default::default(),
+                    state: Default::default(),
+                },
+                read_states: HashSet::new(),
+                state_infos: HashMap::new(),
+                op_blocks: HashMap::new(),
+                quarantine: HashMap::new(),
+                last_compaction: Instant::now(),
+                journal: BTreeMap::new(),
+                skipped_ops: BTreeSet::new(),
+                signing_key_pins: HashMap::new(),
+                signing_key_pins_changed: false,
+                high_water: VClock::new(),
+            }),
+            apply_ops_lock: AsyncMutex::new(()),
+            journal_lock: AsyncMutex::new(()),
+            compact_lock: AsyncMutex::new(()),
+            key_pins_lock: AsyncMutex::new(()),
+            compaction_policy,
+            subscribers: Subscribers::new(),
+            background_errors: LockBox::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+            signing_key_published: AtomicBool::new(false),
+            high_water_checked: AtomicBool::new(false),
+            read_only,
+            accept_rollback,
+        }
+    }
+
+    /// Opens the document `id` of the repository, or returns it if it's open already. Documents
+    /// share the keys, the remote meta and the local actor with the repository, but have their
+    /// own ops, states, compaction, quarantine and change events. The data versions, the
+    /// compaction policy, the read only mode and `accept_rollback` are the ones of `self`,
+    /// `migrations` upgrade blocks of older data versions into `D`.
+    ///
+    /// The returned `Core` syncs only its own document, `Core::run` needs to be called for every
+    /// open document.
+    pub async fn document<D>(
+        self: &Arc<Self>,
+        id: Uuid,
+        migrations: Migrations<D>,
+    ) -> Result<Arc<Core<D, ST, C, KC>>>
+    where
+        D: 'static
+            + CmRDT
+            + CvRDT
+            + Default
+            + Serialize
+            + DeserializeOwned
+            + Clone
+            + Debug
+            + Send
+            + Sync,
+        <D as CmRDT>::Op: 'static + Serialize + DeserializeOwned + Clone + Send,
+    {
+        if self.closed.load(atomic::Ordering::SeqCst) {
+            return Err(Error::Closed);
+        }
+
+        let open_document = self.shared.with(|shared| {
+            shared
+                .documents
+                .get(&Some(id))
+                .and_then(|document| document.upgrade())
+        });
+        if let Some(document) = open_document {
+            return downcast_document(id, document);
+        }
+
+        let document = Arc::new(Core::new(
+            Some(id),
+            self.storage.document(id)?,
+            self.cryptor.clone(),
+            self.key_cryptor.clone(),
+            self.shared.clone(),
+            self.supported_data_versions.clone(),
+            self.current_data_version,
+            migrations,
+            self.compaction_policy.clone(),
+            self.read_only,
+            self.accept_rollback,
+        ));
+        document.load_cache().await?;
+        document.load_journal().await?;
+        document.load_high_water().await?;
+        document.load_key_pins().await?;
+        document.read_remote_().await?;
+        document.try_publish_signing_key().await;
+
+        self.shared.try_with(move |shared| {
+            if let Some(open_document) = shared
+                .documents
+                .get(&Some(id))
+                .and_then(|document| document.upgrade())
+            {
+                // opened concurrently
+                return downcast_document(id, open_document);
+            }
+
+            let weak: Weak<dyn OpenDocument> = Arc::downgrade(&document);
+            shared.documents.insert(Some(id), weak);
+            Ok(document)
+        })
+    }
+
+    /// Lists the ids of all documents in the remote, besides the default document
+    pub async fn documents(self: &Arc<Self>) -> Result<Vec<Uuid>> {
+        self.storage.list_documents().await
+    }
+
+    /// Id of this document, `None` for the default document of the repository
+    pub fn document_id(self: &Arc<Self>) -> Option<Uuid> {
+        self.document
+    }
+
+    fn ensure_writable(self: &Arc<Self>) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
     pub fn info(self: &Arc<Self>) -> Info {
-        self.data.with(|data| {
-            let actor = data
+        self.shared.with(|shared| {
+            let actor = shared
                 .local_meta
                 .as_ref()
                 .expect("info not set, yet. Do not call this fn in the init phase")
@@ -329,38 +653,315 @@ where
         self.data.with(|data| f(&data.state.state))
     }
 
-    pub async fn compact(self: &Arc<Self>) -> Result<()> {
+    /// Returns a stream of change events. An event is emitted every time remote states or ops got
+    /// merged, local ops got applied or a compaction wrote a new state.
+    pub fn subscribe(
+        self: &Arc<Self>,
+    ) -> impl Stream<Item = ChangeEvent> + Send + Unpin + use<S, ST, C, KC> {
+        self.subscribers.subscribe()
+    }
+
+    /// Returns and clears the failures of the steps that run after a change is committed:
+    /// automatic compactions (`CompactionPolicy`), storing the local cache, publishing the
+    /// signing key and recording the read clock. They don't fail the call that committed the
+    /// change (which might retry and write its ops twice) and are retried later. `run` yields
+    /// them as well. Only the last 32 are kept.
+    pub fn take_background_errors(self: &Arc<Self>) -> Vec<Error> {
+        self.background_errors
+            .with(|errors| errors.drain(..).collect())
+    }
+
+    fn report_background_error(self: &Arc<Self>, err: Error) {
+        self.background_errors.with(|errors| {
+            if errors.len() == MAX_BACKGROUND_ERRORS {
+                errors.pop_front();
+            }
+            errors.push_back(err);
+        });
+    }
+
+    /// Syncs with the remote on every item of `ticks`, e.g. an interval timer of the used
+    /// executor. Every round reads the remote ops and states, and depending on `options` the
+    /// remote meta and a compaction. After a failed round the following ticks are skipped with an
+    /// exponential backoff.
+    ///
+    /// If the storage supports watching (`Storage::watch`), changed remote files are read
+    /// immediately, in between the ticks. These rounds count towards the backoff as well, while
+    /// backing off the changes are left to the next round.
+    ///
+    /// The returned stream yields the result of every round, preceded by the failures of the
+    /// steps that ran after committed changes since the last round (`take_background_errors`).
+    /// It ends after `ticks` ended, so to stop cleanly let `ticks` end (e.g.
+    /// `ticks.take_until(shutdown)`); the current round is finished before. Dropping the stream
+    /// stops immediately.
+    pub fn run<T>(
+        self: &Arc<Self>,
+        ticks: T,
+        options: SyncOptions,
+    ) -> impl Stream<Item = Result<()>> + Send + use<S, ST, C, KC, T>
+    where
+        T: Stream + Send + 'static,
+    {
+        let core = self.clone();
+
+        let ticks = ticks
+            .map(|_| SyncTrigger::Tick)
+            .chain(stream::iter([SyncTrigger::Stop]));
+        let storage_events = core
+            .storage
+            .watch()
+            .unwrap_or_else(|| stream::empty().boxed())
+            .ready_chunks(64)
+            .map(SyncTrigger::Storage);
+        let triggers = stream::select(ticks, storage_events).boxed();
+
+        stream::unfold(
+            (core, triggers, options, SyncState::default()),
+            |(core, mut triggers, options, mut state)| async move {
+                loop {
+                    let res = match triggers.next().await? {
+                        SyncTrigger::Stop => return None,
+                        SyncTrigger::Tick => {
+                            if state.skip_tick() {
+                                continue;
+                            }
+
+                            let res = core.sync_round(state.round, &options).await;
+                            state.round_done(res.is_ok(), &options);
+                            res
+                        }
+                        SyncTrigger::Storage(events) => {
+                            // the next round after the backoff reads the changes as well
+                            if state.backing_off() {
+                                continue;
+                            }
+
+                            let res = core.sync_storage_events(events).await;
+                            state.round_done(res.is_ok(), &options);
+                            res
+                        }
+                    };
+
+                    let mut results: Vec<_> =
+                        core.take_background_errors().into_iter().map(Err).collect();
+                    results.push(res);
+                    return Some((results, (core, triggers, options, state)));
+                }
+            },
+        )
+        .flat_map(stream::iter)
+    }
+
+    async fn sync_storage_events(
+        self: &Arc<Self>,
+        events: Vec<Result<StorageEvent>>,
+    ) -> Result<()> {
+        let mut res = Ok(());
+        let mut read_remote_meta = false;
+        let mut read_remote = false;
+        for event in events {
+            match event {
+                Ok(StorageEvent::Meta) => read_remote_meta = true,
+                Ok(StorageEvent::States | StorageEvent::Ops { .. }) => read_remote = true,
+                // the other events of the batch are still handled
+                Err(err) => res = res.and(Err(err)),
+            }
+        }
+
+        if read_remote_meta {
+            res = res.and(self.read_remote_meta().await);
+        }
+        if read_remote {
+            res = res.and(self.read_remote().await);
+        }
+
+        res
+    }
+
+    async fn sync_round(self: &Arc<Self>, round: u64, options: &SyncOptions) -> Result<()> {
+        let remote_meta_interval = u64::from(options.remote_meta_interval.max(1));
+        if round % remote_meta_interval == 0 {
+            self.read_remote_meta().await?;
+        }
+
         self.read_remote().await?;
 
-        let (clear_text, states_to_remove, ops_to_remove, key) = self.data.try_with(|data| {
-            let clear_text = rmp_serde::to_vec_named(&data.state)?;
+        if let Some(compact_interval) = options.compact_interval.filter(|_| !self.read_only) {
+            let compact_interval = u64::from(compact_interval.max(1));
+            if (round + 1) % compact_interval == 0 {
+                match self.compact().await {
+                    // waits for a state of another device that contains the skipped ops
+                    Err(Error::SkippedOps) => {}
+                    res => res?,
+                }
+            }
+        }
 
-            let states_to_remove = data.read_states.iter().cloned().collect();
+        Ok(())
+    }
 
-            let ops_to_remove = data
-                .state
-                .next_op_versions
+    /// Writes the merged state as a new state file and removes the state and op files it
+    /// contains.
+    ///
+    /// Files are only removed once every actor of the document has read them, so devices that
+    /// compact concurrently or see the removal before the new state (sync lag) don't lose data.
+    /// Every actor records the op clock of its merged state in the remote meta after merging a
+    /// state of another device; files not covered by all clocks are kept and removed by a later
+    /// compaction or `gc`. Actors that no longer sync need to be retired (`retire_actor`), or they
+    /// keep the files forever.
+    ///
+    /// Fails with `Error::SkippedOps` while op blocks are skipped, see `skipped_ops`.
+    pub async fn compact(self: &Arc<Self>) -> Result<()> {
+        let compact_lock = self.compact_lock.lock().await;
+        // `close` holds the lock while it compacts
+        if self.closed.load(atomic::Ordering::SeqCst) {
+            return Err(Error::Closed);
+        }
+        self.compact_locked().await?;
+        mem::drop(compact_lock);
+
+        Ok(())
+    }
+
+    pub fn compaction_stats(self: &Arc<Self>) -> CompactionStats {
+        self.data.with(|data| CompactionStats {
+            op_files: data.op_blocks.len(),
+            op_bytes: data.op_blocks.values().map(|info| info.size).sum(),
+            // superseded states wait until every actor read them, compacting again doesn't help
+            state_files: data
+                .state_infos
                 .iter()
-                .map(|dot| (dot.actor.clone(), dot.counter - 1))
-                .collect();
+                .filter(|(name, info)| {
+                    !data.state_infos.iter().any(|(other_name, other)| {
+                        match other.clock.partial_cmp(&info.clock) {
+                            Some(Ordering::Greater) => true,
+                            Some(Ordering::Equal) => other_name < *name,
+                            _ => false,
+                        }
+                    })
+                })
+                .count(),
+            since_last_compaction: data.last_compaction.elapsed(),
+        })
+    }
 
-            let key = data
-                .keys
-                .as_ref()
-                .unwrap()
-                .val
-                .latest_key()
-                .context("no latest key")?;
+    /// Compacts if the `CompactionPolicy` says so. Skips if a compaction is already running.
+    ///
+    /// Called after the changes of the caller are committed, so a failed compaction doesn't fail
+    /// the caller (which might retry and write its ops twice), it's reported with
+    /// `take_background_errors`. The policy triggers it again after the next change.
+    async fn maybe_compact(self: &Arc<Self>) {
+        if self.read_only
+            || self.closed.load(atomic::Ordering::SeqCst)
+            || self.data.with(|data| !data.skipped_ops.is_empty())
+            || !self
+                .compaction_policy
+                .should_compact(&self.compaction_stats())
+        {
+            return;
+        }
 
-            Ok((clear_text, states_to_remove, ops_to_remove, key))
-        })?;
+        let Some(compact_lock) = self.compact_lock.try_lock() else {
+            return;
+        };
+        let res = self.compact_locked().await;
+        mem::drop(compact_lock);
 
-        let data_enc = self.cryptor.encrypt(key.key(), clear_text).await.unwrap();
+        if let Err(err) = res {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(document = ?self.document, error = %err, "automatic compaction failed");
+            self.report_background_error(err);
+        }
+    }
+
+    /// Needs to be called with the `compact_lock` held
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    async fn compact_locked(self: &Arc<Self>) -> Result<()> {
+        self.ensure_writable()?;
+
+        // the read clocks of the other actors
+        self.read_remote_meta().await?;
+        self.read_remote_().await?;
+
+        // the new state would contain the ops after a skipped one, but not the skipped one
+        let clock = self.data.try_with(|data| {
+            if !data.skipped_ops.is_empty() {
+                return Err(Error::SkippedOps);
+            }
+            Ok(data.state.next_op_versions.clone())
+        })?;
+        self.record_read_clock(clock.clone()).await?;
+
+        let key = self.shared.try_with(|shared| shared.latest_key())?;
+        let (removable, retired_actors) = self.removable_clock(clock);
+
+        let (clear_text, states_to_remove, ops_to_remove, covered_ops) =
+            self.data.try_with(|data| {
+                let covered_ops = data.state.next_op_versions.clone();
+
+                let mut ops_to_remove = Vec::new();
+                let mut next_op_versions = VClock::new();
+                for dot in covered_ops.iter() {
+                    let actor = *dot.actor;
+                    let removable_versions = removable.get(&actor);
+                    let prunable = retired_actors.get(&actor).is_some_and(|&versions| {
+                        // ops the actor wrote before it learned about its retirement are read
+                        // and compacted like the ones of every other actor
+                        versions == dot.counter
+                            && versions <= removable_versions
+                            && !data.quarantine.contains_key(&BlockId::Op {
+                                actor,
+                                version: versions,
+                            })
+                    });
+                    if prunable {
+                        // all ops of the retired actor are contained in the state and were read
+                        // by every actor, it's pruned from the clock and its ops get removed
+                        if dot.counter > 0 {
+                            ops_to_remove.push((actor, 0..dot.counter));
+                        }
+                    } else {
+                        if removable_versions > 0 {
+                            ops_to_remove.push((actor, 0..removable_versions));
+                        }
+                        next_op_versions.apply(Dot::new(actor, dot.counter));
+                    }
+                }
+                data.state.next_op_versions = next_op_versions;
+
+                let clear_text = rmp_serde::to_vec_named(&data.state)?;
+                let clear_text = VersionBytes::new(self.current_data_version, clear_text);
+
+                let states_to_remove = data
+                    .read_states
+                    .iter()
+                    .filter(|name| {
+                        data.state_infos.get(*name).is_some_and(|info| {
+                            is_removable(&info.clock, &removable, &retired_actors, &covered_ops)
+                        })
+                    })
+                    .cloned()
+                    .collect();
+
+                Ok((clear_text, states_to_remove, ops_to_remove, covered_ops))
+            })?;
+
+        let block = self
+            .encrypt_block(&key, clear_text.serialize(), Some(BlockTarget::State))
+            .await?;
 
-        let enc_data = VersionBytes::new(self.current_data_version, data_enc);
+        #[cfg(feature = "tracing")]
+        let (state_len, removed_states, removed_actors) = (
+            block.as_ref().len(),
+            states_to_remove.len(),
+            ops_to_remove.len(),
+        );
 
         // first store new state
-        let new_state_name = self.storage.store_state(enc_data).await?;
+        let new_state_name = self.storage.store_state(block).await?;
 
         // then remove old states and ops
         let (removed_states, _) = futures::try_join![
@@ -368,199 +969,1652 @@ where
             self.storage.remove_ops(ops_to_remove),
         ]?;
 
-        self.data.with(|data| {
+        let clock = self.data.with(|data| {
             for removed_state in removed_states {
                 data.read_states.remove(&removed_state);
+                data.state_infos.remove(&removed_state);
             }
 
-            data.read_states.insert(new_state_name);
+            data.read_states.insert(new_state_name.clone());
+            data.state_infos.insert(
+                new_state_name.clone(),
+                StateInfo {
+                    key_id: key.id(),
+                    clock: covered_ops.clone(),
+                },
+            );
+
+            data.op_blocks
+                .retain(|(actor, version), _| covered_ops.get(actor) <= *version);
+
+            data.last_compaction = Instant::now();
+
+            data.state.next_op_versions.clone()
         });
 
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            state = %new_state_name,
+            bytes = state_len,
+            removed_states,
+            removed_actors,
+            "compacted"
+        );
+
+        self.subscribers.notify(ChangeEvent {
+            kind: ChangeKind::Compaction,
+            states: vec![new_state_name],
+            ops: Vec::new(),
+            quarantined: Vec::new(),
+            clock,
+        });
+
+        self.store_cache().await;
+
+        self.retire_unused_keys().await?;
+
         Ok(())
     }
 
-    async fn set_keys(self: &Arc<Self>, keys: ReadCtx<Keys, Uuid>) -> Result<()> {
-        self.data.with(|data| {
-            data.keys = Some(keys);
-        });
+    /// Publishes the public signing key of the local actor for this document through the key
+    /// cryptor, if it isn't already. Only members of the repository can publish keys this way.
+    async fn publish_signing_key(self: &Arc<Self>) -> Result<()> {
+        if self.read_only || self.signing_key_published.load(atomic::Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let (actor, public_key, published) = self.shared.try_with(|shared| {
+            let actor = shared.local_actor()?;
+            let public_key = shared.signing_key()?.verifying_key().to_bytes().to_vec();
+            let published = shared.keys()?.actor_keys().iter().any(|key| {
+                key.actor == actor && key.document == self.document && key.public_key == public_key
+            });
+            Ok((actor, public_key, published))
+        })?;
+
+        if !published {
+            // the ops written before are not signed, the local state needs to contain all of them
+            self.read_remote_().await?;
+            let (first_version, unsigned_versions) = self.data.with(|data| {
+                let clock = &data.state.next_op_versions;
+                let unsigned_versions = clock.iter().map(|dot| (*dot.actor, dot.counter)).collect();
+                (clock.get(&actor), unsigned_versions)
+            });
+
+            let keys_ctx = self.shared.try_with(|shared| {
+                let mut keys_ctx = shared.keys.clone().ok_or(Error::NoKey)?;
+                keys_ctx.val.insert_actor_key(ActorKey {
+                    actor,
+                    document: self.document,
+                    public_key,
+                    first_version,
+                    unsigned_versions,
+                });
+                Ok(keys_ctx)
+            })?;
+
+            // give keys to kc, it gives us a new key ctx back
+            self.key_cryptor.set_keys(keys_ctx).await?;
+        }
 
+        self.signing_key_published
+            .store(true, atomic::Ordering::SeqCst);
         Ok(())
     }
 
-    pub async fn read_remote(self: &Arc<Self>) -> Result<()> {
-        let states_read = self.read_remote_states().await?;
-        let ops_read = self.read_remote_ops().await?;
+    /// `publish_signing_key`, a failure (e.g. while the remote is unavailable) is reported with
+    /// `take_background_errors`, it's retried before the next write
+    async fn try_publish_signing_key(self: &Arc<Self>) {
+        if let Err(err) = self.publish_signing_key().await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(document = ?self.document, error = %err, "failed publishing signing key");
+            self.report_background_error(err);
+        }
+    }
+
+    /// Records `clock`, the op clock of the merged state, as the read clock of the local actor in
+    /// the remote meta. Acknowledges the replaced keys along with it, see `retire_unused_keys`.
+    async fn record_read_clock(self: &Arc<Self>, clock: VClock<Uuid>) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        // an op block that is being applied might be encrypted with a key that just got replaced
+        let acked = match self.apply_ops_lock.try_lock() {
+            Some(apply_ops_lock) => {
+                let acked = self.ack_replaced_keys()?;
+                mem::drop(apply_ops_lock);
+                acked
+            }
+            None => false,
+        };
 
-        if states_read || ops_read {
-            // TODO: notify app of state changes
+        let advanced = self.shared.try_with(|shared| {
+            let actor = shared.local_actor()?;
+            Ok(shared
+                .remote_meta
+                .read_clocks
+                .record(actor, self.document, clock))
+        })?;
+        if advanced || acked {
+            self.store_remote_meta().await?;
         }
 
         Ok(())
     }
 
-    async fn read_remote_states(self: &Arc<Self>) -> Result<bool> {
-        let names = self
-            .storage
-            .list_state_names()
-            .await
-            .context("failed getting state entry names while reading remote states")?;
-
-        let (states_to_read, key) = self.data.try_with(|data| {
-            let states_to_read: Vec<_> = names
+    /// Acknowledges in the remote meta that the local actor no longer encrypts blocks of this
+    /// document with any key but the latest ones. Returns `true` if a key was acknowledged for the
+    /// first time. Needs the `apply_ops_lock` to be held.
+    fn ack_replaced_keys(self: &Arc<Self>) -> Result<bool> {
+        let (actor, replaced_key_ids) = self.shared.try_with(|shared| {
+            let keys = shared.keys()?;
+            let latest_key_ids = keys.latest_key_ids();
+            let replaced_key_ids: Vec<_> = keys
+                .key_ids()
                 .into_iter()
-                .filter(|name| !data.read_states.contains(name))
+                .filter(|id| !latest_key_ids.contains(id))
                 .collect();
-
-            let key = data
-                .keys
-                .as_ref()
-                .unwrap()
-                .val
-                .latest_key()
-                .context("no latest key")?;
-
-            Ok((states_to_read, key))
+            Ok((shared.local_actor()?, replaced_key_ids))
         })?;
+        if replaced_key_ids.is_empty() {
+            return Ok(false);
+        }
 
-        let new_states = self
-            .storage
-            .load_states(states_to_read)
-            .await
-            .context("failed loading state content while reading remote states")?;
+        let clock = self.data.with(|data| {
+            // journaled blocks are not written to the remote yet
+            let journaled_key_ids: HashSet<_> = data
+                .journal
+                .keys()
+                .filter_map(|version| data.op_blocks.get(&(actor, *version)))
+                .map(|info| info.key_id)
+                .collect();
+            replaced_key_ids
+                .iter()
+                .all(|id| !journaled_key_ids.contains(id))
+                .then(|| data.read_clock())
+        });
+        let Some(clock) = clock else {
+            return Ok(false);
+        };
 
-        let new_states: Vec<_> = stream::iter(new_states)
-            .map(|(name, state)| {
-                let key = key.clone();
-                async move {
-                    state.ensure_versions_phf(&SUPPORTED_VERSIONS)?;
+        Ok(self.shared.with(|shared| {
+            shared
+                .remote_meta
+                .key_acks
+                .record(actor, self.document, replaced_key_ids, &clock)
+        }))
+    }
 
-                    let clear_text = self
-                        .cryptor
-                        .decrypt(key.key(), state.into())
-                        .await
-                        .with_context(|| format!("failed decrypting remote state {}", name))?;
+    /// Returns the ops that every actor has read, as far as they are contained in `clock`, and
+    /// the retired actors
+    fn removable_clock(
+        self: &Arc<Self>,
+        clock: VClock<Uuid>,
+    ) -> (VClock<Uuid>, HashMap<Uuid, u64>) {
+        self.shared.with(|shared| {
+            let retired_actors = shared.remote_meta.retired_actors(self.document);
+            let removable =
+                shared
+                    .remote_meta
+                    .read_clocks
+                    .removable(self.document, &retired_actors, clock);
+            (removable, retired_actors)
+        })
+    }
 
-                    let clear_text = VersionBytesRef::deserialize(&clear_text)?;
-                    clear_text.ensure_versions(&self.supported_data_versions)?;
+    /// Removes remote files that are no longer needed: states whose ops are all contained in
+    /// another state, op files contained in every remaining state and remote meta files that are
+    /// merged already (they get replaced by a single merged one). Only files known to this device
+    /// are considered, so the remote is read first. Like `compact`, states and ops are only
+    /// removed once every actor has read them.
+    ///
+    /// With `dry_run` nothing is removed, the report lists what would be removed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    pub async fn gc(self: &Arc<Self>, dry_run: bool) -> Result<GcReport> {
+        // compactions remove files as well
+        let compact_lock = self.compact_lock.lock().await;
+
+        self.read_remote_meta().await?;
+        self.read_remote_().await?;
+
+        let (clock, read_clock) = self
+            .data
+            .with(|data| (data.state.next_op_versions.clone(), data.read_clock()));
+        let (removable, retired_actors) = self.removable_clock(read_clock);
 
-                    let state_wrapper: StateWrapper<S> =
-                        rmp_serde::from_slice(clear_text.as_ref())?;
+        let (states, ops) = self.data.with(|data| {
+            let states: Vec<String> = data
+                .state_infos
+                .iter()
+                .filter(|(_, info)| is_removable(&info.clock, &removable, &retired_actors, &clock))
+                .filter(|(name, info)| {
+                    data.state_infos.iter().any(|(other_name, other)| {
+                        match other.clock.partial_cmp(&info.clock) {
+                            Some(Ordering::Greater) => true,
+                            // keep one of the equal states
+                            Some(Ordering::Equal) => other_name < *name,
+                            _ => false,
+                        }
+                    })
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
 
-                    Result::<_>::Ok((name, state_wrapper))
-                }
-            })
-            .buffer_unordered(16)
-            .try_collect()
-            .await?;
+            let retained: Vec<_> = data
+                .state_infos
+                .iter()
+                .filter(|(name, _)| !states.contains(*name))
+                .map(|(_, info)| &info.clock)
+                .collect();
 
-        let states_read = !new_states.is_empty();
+            let ops: Vec<_> = match retained.first() {
+                Some(first) => first
+                    .iter()
+                    .map(|dot| {
+                        let covered = retained
+                            .iter()
+                            .map(|clock| clock.get(dot.actor))
+                            .min()
+                            .unwrap_or(0)
+                            .min(removable.get(dot.actor));
+                        (*dot.actor, 0..covered)
+                    })
+                    .filter(|(_, versions)| !versions.is_empty())
+                    .collect(),
+                None => Vec::new(),
+            };
+
+            (states, ops)
+        });
 
-        self.data.with(|data| {
-            for (name, state_wrapper) in new_states {
-                data.state.state.merge(state_wrapper.state);
-                data.state
-                    .next_op_versions
-                    .merge(state_wrapper.next_op_versions);
-                data.read_states.insert(name);
+        let remote_metas: Vec<_> = self.shared.with(|shared| {
+            if shared.read_remote_metas.len() > 1 {
+                shared.read_remote_metas.iter().cloned().collect()
+            } else {
+                Vec::new()
             }
         });
 
-        Ok(states_read)
-    }
-
-    async fn read_remote_ops(self: &Arc<Self>) -> Result<bool> {
-        let actors = self
-            .storage
-            .list_op_actors()
-            .await
-            .context("failed getting op actor entries while reading remote ops")?;
+        if !dry_run {
+            self.ensure_writable()?;
 
-        let (ops_to_read, key) = self.data.try_with(|data| {
-            let ops_to_read: Vec<_> = actors
-                .into_iter()
-                .map(|actor| (actor, data.state.next_op_versions.get(&actor)))
-                .collect();
+            let (removed_states, _) = futures::try_join![
+                self.storage.remove_states(states.clone()),
+                self.storage.remove_ops(ops.clone()),
+            ]?;
 
-            let key = data
-                .keys
-                .as_ref()
-                .unwrap()
-                .val
-                .latest_key()
-                .context("no latest key")?;
+            if !remote_metas.is_empty() {
+                // writes the merged remote meta and removes the read ones
+                self.store_remote_meta().await?;
+            }
 
-            Ok((ops_to_read, key))
-        })?;
+            self.data.with(|data| {
+                for removed_state in removed_states {
+                    data.read_states.remove(&removed_state);
+                    data.state_infos.remove(&removed_state);
+                }
 
-        let new_ops = self.storage.load_ops(ops_to_read).await?;
+                data.op_blocks.retain(|(actor, version), _| {
+                    !ops.iter()
+                        .any(|(a, versions)| a == actor && versions.contains(version))
+                });
+            });
+        }
 
-        let new_ops: Vec<_> = stream::iter(new_ops)
-            .map(|(actor, version, data)| {
-                let key = key.clone();
-                async move {
-                    data.ensure_versions_phf(&SUPPORTED_VERSIONS)?;
-                    let clear_text = self.cryptor.decrypt(key.key(), data.into()).await.unwrap();
+        mem::drop(compact_lock);
 
-                    let clear_text = VersionBytesRef::deserialize(&clear_text)?;
-                    clear_text.ensure_versions(&self.supported_data_versions)?;
+        Ok(GcReport {
+            dry_run,
+            states,
+            ops,
+            remote_metas,
+        })
+    }
 
-                    let ops: Vec<_> = rmp_serde::from_slice(clear_text.as_ref())?;
+    /// Checks the remote files of this document and the remote meta files: that the names of
+    /// content addressed files match their content, that every state, op and remote meta file
+    /// can be loaded, decrypted and decoded with a supported version, and that the op versions of
+    /// every actor have no gaps after the versions contained in the readable states.
+    ///
+    /// Only reads, the local state and the quarantine are left untouched.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    pub async fn verify(self: &Arc<Self>) -> Result<VerifyReport> {
+        let mut issues = Vec::new();
+
+        let meta_names = self.storage.list_remote_meta_names().await?;
+        let remote_metas = meta_names.len();
+        let meta_issues: Vec<Vec<Issue>> = stream::iter(meta_names)
+            .map(|name| async move {
+                let mut issues = Vec::new();
+                let res = async {
+                    let Some((_, vbox)) = self
+                        .storage
+                        .load_remote_metas(vec![name.clone()])
+                        .await?
+                        .pop()
+                    else {
+                        // removed in the meantime
+                        return Ok(());
+                    };
+
+                    if let Some(expected) = self.storage.content_name(&vbox)
+                        && expected != name
+                    {
+                        issues.push(Issue::NameMismatch {
+                            file: RemoteFile::Meta { name: name.clone() },
+                            expected,
+                        });
+                    }
+
+                    vbox.ensure_versions_phf(&SUPPORTED_VERSIONS)?;
+                    let _: RemoteMeta = rmp_serde::from_slice(vbox.as_ref())?;
+
+                    Result::<()>::Ok(())
+                }
+                .await;
 
-                    Result::<_, Error>::Ok((actor, version, ops))
+                if let Err(err) = res {
+                    issues.push(Issue::Unreadable {
+                        file: RemoteFile::Meta { name },
+                        cause: Arc::new(err),
+                    });
                 }
+                issues
             })
-            .buffered(16)
-            .try_collect()
-            .await?;
-
-        let ops_read = self.data.with(|data| {
-            let mut ops_read = false;
-            for (actor, version, ops) in new_ops {
-                let expected_version = data.state.next_op_versions.get(&actor);
-
-                if version < expected_version {
-                    // already read that version (concurrent call to this fn between us reading
-                    // the ops and processing them)
-                    continue;
+            .buffer_unordered(16)
+            .collect()
+            .await;
+        issues.extend(meta_issues.into_iter().flatten());
+
+        let state_names = self.storage.list_state_names().await?;
+        let states = state_names.len();
+        let state_results: Vec<(Vec<Issue>, Option<VClock<Uuid>>)> = stream::iter(state_names)
+            .map(|name| async move {
+                let mut issues = Vec::new();
+                let res = async {
+                    let Some((_, block)) =
+                        self.storage.load_states(vec![name.clone()]).await?.pop()
+                    else {
+                        return Ok(None);
+                    };
+                    let block = block?;
+
+                    if let Some(expected) = self.storage.content_name(&block)
+                        && expected != name
+                    {
+                        issues.push(Issue::NameMismatch {
+                            file: RemoteFile::Block(BlockId::State { name: name.clone() }),
+                            expected,
+                        });
+                    }
+
+                    let (_, state_wrapper) = self.decrypt_state(&name, block).await?;
+
+                    Result::<_>::Ok(Some(state_wrapper.next_op_versions))
+                }
+                .await;
+
+                match res {
+                    Ok(clock) => (issues, clock),
+                    Err(err) => {
+                        issues.push(Issue::Unreadable {
+                            file: RemoteFile::Block(BlockId::State { name }),
+                            cause: Arc::new(err),
+                        });
+                        (issues, None)
+                    }
                 }
+            })
+            .buffer_unordered(16)
+            .collect()
+            .await;
+
+        let mut covered_ops = VClock::new();
+        for (state_issues, clock) in state_results {
+            issues.extend(state_issues);
+            if let Some(clock) = clock {
+                covered_ops.merge(clock);
+            }
+        }
 
-                if expected_version < version {
-                    return Err(Error::msg(
-                        "Unexpected op version. Got ops in the wrong order? Bug in storage?",
-                    ));
+        let mut op_blocks = 0;
+        for actor in self.storage.list_op_actors().await? {
+            let mut versions = self.storage.list_op_versions(actor).await?;
+            versions.sort_unstable();
+            op_blocks += versions.len();
+
+            // first versions of the consecutive runs, `load_ops` reads a run at once
+            let mut run_starts = Vec::new();
+            let mut next_version = covered_ops.get(&actor);
+            let mut prev_version = None;
+            for version in versions {
+                if next_version < version {
+                    issues.push(Issue::OpGap {
+                        actor,
+                        versions: next_version..version,
+                    });
                 }
+                next_version = next_version.max(version + 1);
 
-                for op in ops {
-                    data.state.state.apply(op);
+                if !prev_version.is_some_and(|prev| prev + 1 == version) {
+                    run_starts.push(version);
                 }
+                prev_version = Some(version);
+            }
 
-                let version_inc = data.state.next_op_versions.inc(actor);
-                data.state.next_op_versions.apply(version_inc);
+            let op_issues: Vec<Vec<Issue>> = stream::iter(run_starts)
+                .map(|first_version| async move {
+                    let blocks = match self.storage.load_ops(vec![(actor, first_version)]).await {
+                        Ok(blocks) => blocks,
+                        Err(err) => {
+                            // the storage doesn't tell which file of the run failed
+                            return vec![Issue::Unreadable {
+                                file: RemoteFile::Block(BlockId::Op {
+                                    actor,
+                                    version: first_version,
+                                }),
+                                cause: Arc::new(err),
+                            }];
+                        }
+                    };
+
+                    let mut issues = Vec::new();
+                    for (actor, version, block) in blocks {
+                        let res = match block {
+                            Ok(block) => self.decrypt_ops(actor, version, block).await.map(|_| ()),
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = res {
+                            issues.push(Issue::Unreadable {
+                                file: RemoteFile::Block(BlockId::Op { actor, version }),
+                                cause: Arc::new(err),
+                            });
+                        }
+                    }
+                    issues
+                })
+                .buffer_unordered(4)
+                .collect()
+                .await;
+            issues.extend(op_issues.into_iter().flatten());
+        }
+
+        Ok(VerifyReport {
+            remote_metas,
+            states,
+            op_blocks,
+            issues,
+        })
+    }
+
+    /// Rebuilds a fresh compacted state from everything that is readable, including blocks that
+    /// were quarantined before, and replaces misnamed remote meta files by a merged one. Blocks
+    /// that are still unreadable and ops after a gap are left in place, the missing files might
+    /// still be syncing. Returns the report of a `verify` after the repair. Fails with
+    /// `Error::SkippedOps` while op blocks are skipped, like `compact`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    pub async fn repair(self: &Arc<Self>) -> Result<VerifyReport> {
+        self.ensure_writable()?;
+
+        let report = self.verify().await?;
+
+        let compact_lock = self.compact_lock.lock().await;
+
+        self.data.with(|data| data.quarantine.clear());
+        self.read_remote_meta().await?;
+        self.compact_locked().await?;
+
+        let misnamed_meta = report.issues.iter().any(|issue| {
+            matches!(
+                issue,
+                Issue::NameMismatch {
+                    file: RemoteFile::Meta { .. },
+                    ..
+                }
+            )
+        });
+        if misnamed_meta {
+            self.store_remote_meta().await?;
+        }
+
+        mem::drop(compact_lock);
+
+        self.verify().await
+    }
+
+    /// Decrypts every state and op file of this document for debugging, e.g. to find out why
+    /// devices diverge. The files are decoded on their own, without merging them into the local
+    /// state; files that fail are listed with their error. Serialize the dump with e.g.
+    /// `DebugDump::to_json`.
+    ///
+    /// The dump contains the clear text, it must not be stored next to the remote.
+    pub async fn debug_dump(self: &Arc<Self>) -> Result<DebugDump<S, S::Op>> {
+        let names = self.storage.list_state_names().await?;
+        let states_res: Vec<Result<DebugState<S>, DebugError>> = stream::iter(names)
+            .map(|name| async move {
+                let res = async {
+                    let Some((_, block)) =
+                        self.storage.load_states(vec![name.clone()]).await?.pop()
+                    else {
+                        return Ok(None);
+                    };
+                    let (key_id, clear_text, unsigned_bound) =
+                        self.decrypt_data(block?, BlockTarget::State).await?;
+                    let state_wrapper = self.decode_state(&clear_text)?;
+                    signing::ensure_unsigned_state(
+                        unsigned_bound.as_ref(),
+                        &state_wrapper.next_op_versions,
+                    )?;
+
+                    Result::<_>::Ok(Some(DebugState {
+                        name: name.clone(),
+                        key_id,
+                        data_version: clear_text.version(),
+                        clock: state_wrapper.next_op_versions,
+                        state: state_wrapper.state,
+                    }))
+                }
+                .await;
+
+                res.transpose().map(|res| {
+                    res.map_err(|err| DebugError::new(Some(BlockId::State { name }), &err))
+                })
+            })
+            .buffer_unordered(16)
+            .filter_map(|res| async move { res })
+            .collect()
+            .await;
+
+        let mut states = Vec::new();
+        let mut errors = Vec::new();
+        for res in states_res {
+            match res {
+                Ok(state) => states.push(state),
+                Err(err) => errors.push(err),
+            }
+        }
+        states.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        let mut blocks = Vec::new();
+        for actor in self.storage.list_op_actors().await? {
+            // the ops of the other actors are still dumped
+            match storage::load_all_ops(&self.storage, actor).await {
+                Ok(actor_blocks) => blocks.extend(actor_blocks),
+                Err(err) => errors.push(DebugError::for_actor(actor, &err)),
+            }
+        }
+
+        let ops_res: Vec<_> = stream::iter(blocks)
+            .map(|(actor, version, block)| async move {
+                let res = async {
+                    let (key_id, clear_text, _) = self
+                        .decrypt_data(block?, BlockTarget::Op { actor, version })
+                        .await?;
+                    let ops = self.decode_ops(&clear_text)?;
+
+                    Result::<_>::Ok(DebugOpBlock {
+                        actor,
+                        version,
+                        key_id,
+                        data_version: clear_text.version(),
+                        ops,
+                    })
+                }
+                .await;
+
+                res.map_err(|err| DebugError::new(Some(BlockId::Op { actor, version }), &err))
+            })
+            .buffered(16)
+            .collect()
+            .await;
+
+        let mut op_blocks = Vec::new();
+        for res in ops_res {
+            match res {
+                Ok(block) => op_blocks.push(block),
+                Err(err) => errors.push(err),
+            }
+        }
+
+        let actor = self.shared.try_with(|shared| shared.local_actor())?;
+        let (state, clock) = self.data.with(|data| {
+            (
+                data.state.state.clone(),
+                data.state.next_op_versions.clone(),
+            )
+        });
+
+        Ok(DebugDump {
+            actor,
+            state,
+            clock,
+            states,
+            op_blocks,
+            errors,
+        })
+    }
+
+    /// Shuts down gracefully: waits for in-flight writes, writes the journal and the local cache,
+    /// compacts if `compact` is set (a read only core doesn't) and calls the `shutdown` hooks of
+    /// the backends. Writing ops fails with `Error::Closed` afterwards.
+    ///
+    /// Closing the repository (the default document) closes its open documents first, then it
+    /// shuts down the shared cryptor and key cryptor. Every step runs even if an earlier one
+    /// failed, the first error is returned.
+    pub async fn close(self: &Arc<Self>, compact: bool) -> Result<()> {
+        self.closed.store(true, atomic::Ordering::SeqCst);
+
+        let mut res = Ok(());
+
+        if self.document.is_none() {
+            let documents: Vec<_> = self.shared.with(|shared| {
+                shared
+                    .documents
+                    .iter()
+                    .filter(|(id, _)| id.is_some())
+                    .filter_map(|(_, document)| document.upgrade())
+                    .collect()
+            });
+            for document in documents {
+                res = res.and(document.close_document(compact).await);
+            }
+        }
+
+        // wait for in-flight writes, new ones fail now
+        let apply_ops_lock = self.apply_ops_lock.lock().await;
+        let compact_lock = self.compact_lock.lock().await;
+
+        if compact && !self.read_only {
+            res = res.and(self.compact_locked().await);
+        } else {
+            // if the remote is not available, the journal is written after the next open
+            let _ = self.flush_journal().await;
+            res = res.and(self.store_cache_().await);
+        }
+
+        res = res.and(self.storage.shutdown().await);
+        if self.document.is_none() {
+            let (cryptor, key_cryptor) =
+                futures::join!(self.cryptor.shutdown(), self.key_cryptor.shutdown());
+            res = res.and(cryptor).and(key_cryptor);
+        }
+
+        mem::drop(compact_lock);
+        mem::drop(apply_ops_lock);
+
+        res
+    }
+
+    /// Retires `actor`, e.g. a device that is no longer used. A state containing all of its ops is
+    /// compacted first, once every actor read it, the next compaction removes its op files and
+    /// prunes it from the op clock. A retired actor can't write ops anymore,
+    /// there is no way back.
+    ///
+    /// Ops the actor wrote after that state, before it learned about its retirement, are still
+    /// read, but keep it in the op clock. Retiring it again after they were read prunes it.
+    ///
+    /// Applies to this document only, needs to be called for every document of the repository.
+    /// Fails with `Error::RetireLocalActor` for the actor of this device.
+    pub async fn retire_actor(self: &Arc<Self>, actor: Uuid) -> Result<()> {
+        self.ensure_writable()?;
+
+        if self.shared.try_with(|shared| shared.local_actor())? == actor {
+            return Err(Error::RetireLocalActor);
+        }
+
+        self.compact().await?;
+
+        let versions = self
+            .data
+            .with(|data| data.state.next_op_versions.get(&actor));
+
+        self.shared.with(|shared| {
+            shared.remote_meta.retired_actors.insert(RetiredActor {
+                actor,
+                document: self.document,
+                versions,
+            });
+        });
+
+        self.store_remote_meta().await
+    }
+
+    /// Generates a new data key and publishes it through the key cryptor. All ops and states
+    /// written after this are encrypted with the new key. Existing states get re-encrypted by the
+    /// next compaction. The old keys are retired by a later compaction, once every actor switched
+    /// to the new key and read the blocks written before.
+    ///
+    /// Returns the id of the new key.
+    pub async fn rotate_key(self: &Arc<Self>) -> Result<Uuid> {
+        self.ensure_writable()?;
+
+        let new_key = Key::new(self.cryptor.gen_key().await?);
+        let key_id = new_key.id();
+        let actor = self.info().actor();
+
+        let keys_ctx = self.shared.try_with(|shared| {
+            let mut keys_ctx = shared.keys.clone().ok_or(Error::NoKey)?;
+            keys_ctx.val.insert_latest_key(actor, new_key);
+            Ok(keys_ctx)
+        })?;
+
+        // give keys to kc, it gives us a new key ctx back
+        self.key_cryptor.set_keys(keys_ctx).await?;
+
+        Ok(key_id)
+    }
+
+    /// Removes all keys, except the latest ones, that are not referenced by any state or op
+    /// block known to this device. Other devices might still hold blocks encrypted with a key,
+    /// so a key is only removed once every not retired actor of every document acknowledged
+    /// that it no longer encrypts with it (`record_read_clock`), and every actor has read the
+    /// ops written until then. Skipped as long as not all documents of the repository are open,
+    /// because the blocks of the other documents are unknown.
+    async fn retire_unused_keys(self: &Arc<Self>) -> Result<()> {
+        let remote_documents = self.storage.list_documents().await?;
+
+        let open_documents: HashMap<_, _> = self.shared.with(|shared| {
+            shared
+                .documents
+                .iter()
+                .filter_map(|(id, document)| Some((*id, document.upgrade()?)))
+                .collect()
+        });
+
+        let all_open = remote_documents
+            .into_iter()
+            .map(Some)
+            .chain([None])
+            .all(|id| open_documents.contains_key(&id));
+        if !all_open {
+            return Ok(());
+        }
+
+        let used_key_ids: HashSet<_> = open_documents
+            .values()
+            .flat_map(|document| document.used_key_ids())
+            .collect();
+        let op_clocks: Vec<_> = open_documents
+            .iter()
+            .map(|(id, document)| (*id, document.op_clock()))
+            .collect();
+
+        let keys_ctx = self.shared.try_with(|shared| {
+            let keys = shared.keys()?;
+            let latest_key_ids = keys.latest_key_ids();
+
+            let unused_key_ids: Vec<_> = keys
+                .key_ids()
+                .into_iter()
+                .filter(|id| !latest_key_ids.contains(id) && !used_key_ids.contains(id))
+                .filter(|id| {
+                    op_clocks.iter().all(|(document, clock)| {
+                        shared.remote_meta.key_replaced(*id, *document, clock)
+                    })
+                })
+                .collect();
+
+            if unused_key_ids.is_empty() {
+                return Ok(None);
+            }
+
+            let mut keys_ctx = shared.keys.clone().ok_or(Error::NoKey)?;
+            keys_ctx.val.remove_keys(&unused_key_ids);
+            Ok(Some(keys_ctx))
+        })?;
+
+        if let Some(keys_ctx) = keys_ctx {
+            self.key_cryptor.set_keys(keys_ctx).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_keys(self: &Arc<Self>, keys: ReadCtx<Keys, Uuid>) -> Result<()> {
+        self.shared.with(|shared| {
+            shared.keys = Some(keys);
+        });
+
+        Ok(())
+    }
+
+    /// Reads and merges the new remote states and ops. Fails with `Error::RolledBack` if the
+    /// remote presents less than this device has seen of it before.
+    pub async fn read_remote(self: &Arc<Self>) -> Result<()> {
+        self.read_remote_().await?;
+
+        // a local compaction replaces states, the check would see the new state before its clock
+        let compact_lock = self.compact_lock.lock().await;
+        let accept =
+            self.accept_rollback && !self.high_water_checked.load(atomic::Ordering::SeqCst);
+        self.check_high_water(accept).await?;
+        self.high_water_checked
+            .store(true, atomic::Ordering::SeqCst);
+        mem::drop(compact_lock);
+
+        self.maybe_compact().await;
+
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    async fn read_remote_(self: &Arc<Self>) -> Result<()> {
+        self.flush_journal().await?;
+
+        let (states_read, mut quarantined) = self.read_remote_states().await?;
+        let (ops_read, ops_quarantined) = self.read_remote_ops().await?;
+        quarantined.extend(ops_quarantined);
+
+        if !states_read.is_empty() || !ops_read.is_empty() || !quarantined.is_empty() {
+            let (clock, read_clock) = self
+                .data
+                .with(|data| (data.state.next_op_versions.clone(), data.read_clock()));
+            let state_merged = !states_read.is_empty();
+
+            self.subscribers.notify(ChangeEvent {
+                kind: ChangeKind::Remote,
+                states: states_read,
+                ops: ops_read,
+                quarantined,
+                clock: clock.clone(),
+            });
+
+            self.store_cache().await;
+
+            if state_merged {
+                // another device compacted, it's waiting for us to read the state before it
+                // removes the compacted files. The merge is committed already, a failure only
+                // delays the removal until the clock is recorded by the next merge or compaction.
+                if let Err(err) = self.record_read_clock(read_clock).await {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        document = ?self.document,
+                        error = %err,
+                        "failed recording read clock"
+                    );
+                    self.report_background_error(err);
+                }
+            }
+        }
+
+        // keys pinned while reading, stored after the notification, because the blocks are
+        // merged already. A failure is retried by the next read.
+        self.store_key_pins().await?;
+
+        Ok(())
+    }
+
+    /// Loads the pinned signing keys of the document, see `store_key_pins`
+    async fn load_key_pins(self: &Arc<Self>) -> Result<()> {
+        let Some(pins) = self.storage.load_local_key_pins().await? else {
+            return Ok(());
+        };
+        pins.ensure_versions_phf(&SUPPORTED_VERSIONS)?;
+        let pins: HashMap<Uuid, Vec<u8>> = rmp_serde::from_slice(pins.as_ref())?;
+
+        self.data.with(|data| data.signing_key_pins = pins);
+
+        Ok(())
+    }
+
+    /// Stores the pinned signing keys, if they changed. They are not part of the local cache,
+    /// which is dropped when it can't be read, a lost pin would let the next key published for
+    /// the actor get pinned.
+    async fn store_key_pins(self: &Arc<Self>) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        // an older snapshot of the pins must not overwrite a newer one
+        let key_pins_lock = self.key_pins_lock.lock().await;
+
+        let pins = self.data.with(|data| {
+            mem::take(&mut data.signing_key_pins_changed).then(|| data.signing_key_pins.clone())
+        });
+        let Some(pins) = pins else {
+            return Ok(());
+        };
+
+        let res = async {
+            let vbox = VersionBytes::new(CURRENT_VERSION, rmp_serde::to_vec_named(&pins)?);
+            self.storage.store_local_key_pins(vbox).await
+        }
+        .await;
+        if res.is_err() {
+            self.data.with(|data| data.signing_key_pins_changed = true);
+        }
+
+        mem::drop(key_pins_lock);
+
+        res
+    }
+
+    /// Loads the high-water mark of the document, see `check_high_water`
+    async fn load_high_water(self: &Arc<Self>) -> Result<()> {
+        let Some(high_water) = self.storage.load_local_high_water().await? else {
+            return Ok(());
+        };
+        high_water.ensure_versions_phf(&SUPPORTED_VERSIONS)?;
+        let high_water: VClock<Uuid> = rmp_serde::from_slice(high_water.as_ref())?;
+
+        self.data.with(|data| data.high_water = high_water);
+
+        Ok(())
+    }
+
+    /// Compares what the remote presents with the high-water mark, the op clock of everything
+    /// this device has seen of the document. An intact remote never presents less, ops are
+    /// removed only after they are covered by a state every actor has read. A remote that serves
+    /// an older set of states or lost ops fails with `Error::RolledBack`, unless `accept` is set,
+    /// then the mark is reset to what the remote presents. Ops covered by the read clock of this
+    /// device are not required, the state that replaced them might not be synced, yet.
+    ///
+    /// The clocks of states that are not read (yet), e.g. quarantined ones or ones whose signer
+    /// isn't known, are unknown. While there are any, the check only confirms what the read
+    /// states and the ops cover, it doesn't fail. Ops are only checked if the storage can list
+    /// them (`Storage::list_op_versions`).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    async fn check_high_water(self: &Arc<Self>, accept: bool) -> Result<()> {
+        let names = self.storage.list_state_names().await?;
+
+        let (mut presented, complete, high_water) = self.data.with(|data| {
+            let mut presented = VClock::new();
+            let mut complete = true;
+            for name in names {
+                match data.state_infos.get(&name) {
+                    Some(info) => presented.merge(info.clock.clone()),
+                    None => complete = false,
+                }
+            }
+            (presented, complete, data.high_water.clone())
+        });
+
+        let (actor, retired_actors, read_clock) = self.shared.try_with(|shared| {
+            let actor = shared.local_actor()?;
+            let retired_actors = shared.remote_meta.retired_actors(self.document);
+            let read_clock = shared
+                .remote_meta
+                .read_clocks
+                .get(actor, self.document)
+                .cloned()
+                .unwrap_or_default();
+            Ok((actor, retired_actors, read_clock))
+        })?;
+
+        let mut rolled_back = false;
+        for dot in high_water.iter() {
+            let (op_actor, seen) = (*dot.actor, dot.counter);
+            // retired actors get pruned from the state clocks
+            if seen <= presented.get(&op_actor) || retired_actors.contains_key(&op_actor) {
+                continue;
+            }
+
+            // the ops not covered by a state need to be there
+            let versions: HashSet<_> = match self.storage.list_op_versions(op_actor).await {
+                Ok(versions) => versions.into_iter().collect(),
+                Err(Error::Unsupported(_)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(
+                        actor = %op_actor,
+                        "listing op versions not supported, skipping rollback check"
+                    );
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            // ops covered by the read clock of this device were removable, another device could
+            // have removed them before its state containing them synced
+            let mut version = presented.get(&op_actor).max(read_clock.get(&op_actor));
+            while versions.contains(&version) {
+                version += 1;
+            }
+
+            if version < seen {
+                if !complete {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(
+                        actor = %op_actor,
+                        seen,
+                        presented = version,
+                        "states not read, skipping rollback check"
+                    );
+                    continue;
+                }
+
+                if !accept {
+                    return Err(Error::RolledBack {
+                        actor: op_actor,
+                        seen,
+                        presented: version,
+                    });
+                }
+
+                #[cfg(feature = "tracing")]
+                tracing::warn!(actor = %op_actor, seen, presented = version, "accepted rollback");
+
+                rolled_back = true;
+            }
+            presented.apply(Dot::new(op_actor, version));
+        }
+
+        let high_water = self.data.with(|data| {
+            let journaled = data.journal.keys().next().copied();
+            let observed = data
+                .state
+                .next_op_versions
+                .iter()
+                .map(|dot| match journaled {
+                    // journaled ops are not written to the remote, yet
+                    Some(journaled) if *dot.actor == actor => {
+                        Dot::new(*dot.actor, dot.counter.min(journaled))
+                    }
+                    _ => Dot::new(*dot.actor, dot.counter),
+                })
+                .filter(|dot| dot.counter > 0)
+                .fold(VClock::new(), |mut observed, dot| {
+                    observed.apply(dot);
+                    observed
+                });
+
+            if rolled_back {
+                data.high_water = presented;
+            } else if observed <= data.high_water {
+                return None;
+            } else {
+                data.high_water.merge(observed);
+            }
+            Some(data.high_water.clone())
+        });
+
+        match high_water {
+            // a read only core keeps the mark in memory
+            Some(high_water) if !self.read_only => {
+                let vbox =
+                    VersionBytes::new(CURRENT_VERSION, rmp_serde::to_vec_named(&high_water)?);
+                self.storage.store_local_high_water(vbox).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Resumes from the local cache. A cache that can't be read anymore (e.g. its key got
+    /// retired) is ignored, it gets replaced the next time the state changes.
+    ///
+    /// The remote meta is not cached, it's needed to get the keys that decrypt the cache.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    async fn load_cache(self: &Arc<Self>) -> Result<()> {
+        let Some(block) = self.storage.load_local_cache().await? else {
+            return Ok(());
+        };
+
+        let cache = async {
+            // the cache is local, it's not signed
+            let (_, clear_text, _) = self.decrypt_block(block, None).await?;
+
+            let clear_text = VersionBytesRef::deserialize(&clear_text)?;
+            // an outdated cache is dropped, the blocks get migrated while reading them again
+            clear_text.ensure_versions(&[self.current_data_version])?;
+
+            let cache: LocalCache<S> = rmp_serde::from_slice(clear_text.as_ref())?;
+
+            Result::<_>::Ok(cache)
+        }
+        .await;
+        let Ok(cache) = cache else {
+            return Ok(());
+        };
+
+        self.data.with(|data| {
+            data.state = cache.state;
+            data.read_states = cache.state_infos.keys().cloned().collect();
+            data.state_infos = cache.state_infos;
+            data.op_blocks = cache
+                .op_blocks
+                .into_iter()
+                .map(|(actor, version, info)| ((actor, version), info))
+                .collect();
+            data.skipped_ops = cache.skipped_ops;
+        });
+
+        Ok(())
+    }
+
+    /// Stores the state and the read blocks in the local cache, ops applied by `apply_ops` are
+    /// cached with the next read or compaction, until then they are read from the remote again.
+    ///
+    /// Called after the changes are committed. The cache only saves reading the remote again, so
+    /// a failure doesn't fail the caller, it's reported with `take_background_errors`.
+    async fn store_cache(self: &Arc<Self>) {
+        if let Err(err) = self.store_cache_().await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(document = ?self.document, error = %err, "failed storing local cache");
+            self.report_background_error(err);
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    async fn store_cache_(self: &Arc<Self>) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        let key = self.shared.try_with(|shared| shared.latest_key())?;
+
+        let clear_text = self.data.try_with(|data| {
+            let cache = LocalCacheRef {
+                state: &data.state,
+                state_infos: &data.state_infos,
+                op_blocks: data
+                    .op_blocks
+                    .iter()
+                    .map(|(&(actor, version), info)| (actor, version, info))
+                    .collect(),
+                skipped_ops: &data.skipped_ops,
+            };
+            let clear_text = rmp_serde::to_vec_named(&cache)?;
+            Ok(VersionBytes::new(self.current_data_version, clear_text))
+        })?;
+
+        let block = self
+            .encrypt_block(&key, clear_text.serialize(), None)
+            .await?;
+
+        self.storage.store_local_cache(block).await
+    }
+
+    /// Returns the names of the merged states and the ids of the newly quarantined states
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    async fn read_remote_states(self: &Arc<Self>) -> Result<(Vec<String>, Vec<BlockId>)> {
+        let names = self.storage.list_state_names().await?;
+
+        let states_to_read = self.data.with(|data| {
+            let states_to_read: Vec<_> = names
+                .into_iter()
+                .filter(|name| {
+                    !data.read_states.contains(name)
+                        && !data
+                            .quarantine
+                            .contains_key(&BlockId::State { name: name.clone() })
+                })
+                .collect();
+            states_to_read
+        });
+
+        let new_states = self.storage.load_states(states_to_read).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            count = new_states.len(),
+            bytes = new_states
+                .iter()
+                .filter_map(|(_, block)| block.as_ref().ok())
+                .map(|block| block.as_ref().len())
+                .sum::<usize>(),
+            "loaded states"
+        );
+
+        let new_states: Vec<_> = stream::iter(new_states)
+            .map(|(name, state)| async move {
+                let res = match state {
+                    Ok(state) => self.decrypt_state(&name, state).await,
+                    Err(err) => {
+                        Err(err.in_file(RemoteFile::Block(BlockId::State { name: name.clone() })))
+                    }
+                };
+                (name, res)
+            })
+            .buffer_unordered(16)
+            .collect()
+            .await;
+
+        let res = self.data.with(|data| {
+            let mut states_read = Vec::with_capacity(new_states.len());
+            let mut quarantined = Vec::new();
+            for (name, res) in new_states {
+                match res {
+                    Ok((key_id, state_wrapper)) => {
+                        let clock = state_wrapper.next_op_versions;
+                        data.state.state.merge(state_wrapper.state);
+                        data.state.next_op_versions.merge(clock.clone());
+                        // another device could read the skipped ops
+                        data.skipped_ops
+                            .retain(|(actor, version)| clock.get(actor) <= *version);
+                        data.read_states.insert(name.clone());
+                        data.state_infos
+                            .insert(name.clone(), StateInfo { key_id, clock });
+                        states_read.push(name);
+                    }
+                    // the key isn't synced yet, read again the next time
+                    Err(err) if is_retryable(&err) => {}
+                    Err(err) => {
+                        let id = BlockId::State { name };
+                        data.quarantine_block(id.clone(), err);
+                        quarantined.push(id);
+                    }
+                }
+            }
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                merged = states_read.len(),
+                quarantined = quarantined.len(),
+                "merged states"
+            );
+
+            (states_read, quarantined)
+        });
+
+        Ok(res)
+    }
+
+    /// Returns `(actor, version)` of the applied op blocks and the ids of the newly quarantined
+    /// op blocks
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    async fn read_remote_ops(self: &Arc<Self>) -> Result<(Vec<(Uuid, u64)>, Vec<BlockId>)> {
+        let actors = self.storage.list_op_actors().await?;
+
+        let retired_actors = self
+            .shared
+            .with(|shared| shared.remote_meta.retired_actors(self.document));
+
+        let ops_to_read = self.data.with(|data| {
+            let ops_to_read: Vec<_> = actors
+                .into_iter()
+                .map(|actor| (actor, data.state.next_op_versions.get(&actor)))
+                // retired actors are read like the others until they are pruned from the clock,
+                // `0` means all their ops are contained in the state
+                .filter(|(actor, version)| !(*version == 0 && retired_actors.contains_key(actor)))
+                // the next op of the actor is quarantined, no need to read the following ones
+                .filter(|&(actor, version)| {
+                    !data
+                        .quarantine
+                        .contains_key(&BlockId::Op { actor, version })
+                })
+                .collect();
+            ops_to_read
+        });
+
+        let new_ops = self.storage.load_ops(ops_to_read).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            count = new_ops.len(),
+            bytes = new_ops
+                .iter()
+                .filter_map(|(_, _, block)| block.as_ref().ok())
+                .map(|block| block.as_ref().len())
+                .sum::<usize>(),
+            "loaded op blocks"
+        );
+
+        let new_ops: Vec<_> = stream::iter(new_ops)
+            .map(|(actor, version, data)| async move {
+                let (size, res) = match data {
+                    Ok(data) => (
+                        data.as_ref().len() as u64,
+                        self.decrypt_ops(actor, version, data).await,
+                    ),
+                    Err(err) => (
+                        0,
+                        Err(err.in_file(RemoteFile::Block(BlockId::Op { actor, version }))),
+                    ),
+                };
+                (actor, version, size, res)
+            })
+            .buffered(16)
+            .collect()
+            .await;
+
+        let ops_read = self.data.with(|data| {
+            let mut ops_read = Vec::new();
+            let mut quarantined = Vec::new();
+            // actors whose op blocks need a key that isn't synced yet, their ops are read again
+            // the next time
+            let mut retry_actors = HashSet::new();
+            for (actor, version, size, res) in new_ops {
+                if retry_actors.contains(&actor) {
+                    continue;
+                }
+
+                let expected_version = data.state.next_op_versions.get(&actor);
+
+                if version < expected_version {
+                    // already read that version (concurrent call to this fn between us reading
+                    // the ops and processing them)
+                    continue;
+                }
+
+                let expected_id = BlockId::Op {
+                    actor,
+                    version: expected_version,
+                };
+                if data.quarantine.contains_key(&expected_id) {
+                    // an earlier op of this actor is quarantined, skip the following ones
+                    continue;
+                }
+
+                if expected_version < version {
+                    return Err(Error::UnexpectedOpVersion {
+                        actor,
+                        expected: expected_version,
+                        got: version,
+                    });
+                }
+
+                let (key_id, ops) = match res {
+                    Ok(res) => res,
+                    Err(err) if is_retryable(&err) => {
+                        retry_actors.insert(actor);
+                        continue;
+                    }
+                    Err(err) => {
+                        data.quarantine_block(expected_id.clone(), err);
+                        quarantined.push(expected_id);
+                        continue;
+                    }
+                };
+
+                for op in ops {
+                    data.state.state.apply(op);
+                }
+
+                let version_inc = data.state.next_op_versions.inc(actor);
+                data.state.next_op_versions.apply(version_inc);
+
+                data.op_blocks
+                    .insert((actor, version), OpBlockInfo { key_id, size });
+                ops_read.push((actor, version));
+            }
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                applied = ops_read.len(),
+                quarantined = quarantined.len(),
+                "applied op blocks"
+            );
+
+            Ok((ops_read, quarantined))
+        })?;
+
+        Ok(ops_read)
+    }
+
+    /// Decrypts the state block `name`, returns the key id and the state
+    async fn decrypt_state(
+        self: &Arc<Self>,
+        name: &str,
+        block: VersionBytes,
+    ) -> Result<(Uuid, StateWrapper<S>)> {
+        let res = async {
+            let (key_id, clear_text, unsigned_bound) =
+                self.decrypt_data(block, BlockTarget::State).await?;
+            let state = self.decode_state(&clear_text)?;
+            signing::ensure_unsigned_state(unsigned_bound.as_ref(), &state.next_op_versions)?;
+            Result::<_>::Ok((key_id, state))
+        }
+        .await;
+        res.map_err(|err| {
+            err.in_file(RemoteFile::Block(BlockId::State {
+                name: name.to_owned(),
+            }))
+        })
+    }
+
+    /// Decrypts the op block `version` of `actor`, returns the key id and the ops
+    async fn decrypt_ops(
+        self: &Arc<Self>,
+        actor: Uuid,
+        version: u64,
+        block: VersionBytes,
+    ) -> Result<(Uuid, Vec<S::Op>)> {
+        let res = async {
+            let target = BlockTarget::Op { actor, version };
+            let (key_id, clear_text, _) = self.decrypt_data(block, target).await?;
+            Result::<_>::Ok((key_id, self.decode_ops(&clear_text)?))
+        }
+        .await;
+        res.map_err(|err| err.in_file(RemoteFile::Block(BlockId::Op { actor, version })))
+    }
+
+    /// Decrypts a state or op block, checks its signature and data version, returns the key id,
+    /// the clear text and for an unsigned state the clock it needs to be covered by, see
+    /// `ActorKeys::verify`
+    async fn decrypt_data(
+        self: &Arc<Self>,
+        block: VersionBytes,
+        target: BlockTarget,
+    ) -> Result<(Uuid, VersionBytes, Option<VClock<Uuid>>)> {
+        let (key_id, clear_text, unsigned_bound) = self.decrypt_block(block, Some(target)).await?;
+
+        let clear_text = VersionBytes::deserialize(&clear_text)?;
+        if let Err(err) = clear_text.ensure_versions(&self.supported_data_versions) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(version = %clear_text.version(), "unsupported data version");
+            return Err(err.into());
+        }
+
+        Ok((key_id, clear_text, unsigned_bound))
+    }
+
+    fn decode_state(self: &Arc<Self>, clear_text: &VersionBytes) -> Result<StateWrapper<S>> {
+        match self
+            .migrations
+            .decode_state(clear_text.version(), clear_text.as_ref())
+        {
+            Some(state_wrapper) => state_wrapper,
+            None => Ok(rmp_serde::from_slice(clear_text.as_ref())?),
+        }
+    }
+
+    fn decode_ops(self: &Arc<Self>, clear_text: &VersionBytes) -> Result<Vec<S::Op>> {
+        match self
+            .migrations
+            .decode_ops(clear_text.version(), clear_text.as_ref())
+        {
+            Some(ops) => ops,
+            None => Ok(rmp_serde::from_slice(clear_text.as_ref())?),
+        }
+    }
+
+    /// Loads the journal and applies the journaled ops the cached state doesn't contain yet. If
+    /// the cached state is behind the journal, the remote isn't read here, the journal is applied
+    /// by the next sync (see `apply_ops_locked`).
+    async fn load_journal(self: &Arc<Self>) -> Result<()> {
+        let mut journal = self.storage.load_journal().await?;
+        journal.sort_unstable_by_key(|(version, _)| *version);
+
+        let actor = self.shared.try_with(|shared| shared.local_actor())?;
+        let last_version = journal.last().map(|(version, _)| *version);
+
+        for (version, block) in journal {
+            let next_version = self
+                .data
+                .with(|data| data.state.next_op_versions.get(&actor));
+
+            let res = match block {
+                // already contained in the cached state
+                Ok(block) if version < next_version => Ok((block, None)),
+                Ok(block) => self
+                    .decrypt_ops(actor, version, block.clone())
+                    .await
+                    .map(|res| (block, Some(res))),
+                Err(err) => Err(err),
+            };
+            let (block, decrypted) = match res {
+                Ok(res) => res,
+                Err(Error::Decrypt(_) | Error::Decode(_)) if Some(version) == last_version => {
+                    // an interrupted write left a torn entry, its ops were never applied
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(version, "dropping torn journal entry");
+                    if !self.read_only {
+                        self.storage.remove_journal_op(version).await?;
+                    }
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let size = block.as_ref().len() as u64;
+            self.data.with(|data| {
+                if let Some((key_id, ops)) = decrypted {
+                    if version == next_version {
+                        for op in ops {
+                            data.state.state.apply(op);
+                        }
+
+                        let version_inc = data.state.next_op_versions.inc(actor);
+                        data.state.next_op_versions.apply(version_inc);
+                    }
+
+                    // recorded for blocks that aren't applied yet as well, so their key isn't
+                    // retired
+                    data.op_blocks
+                        .insert((actor, version), OpBlockInfo { key_id, size });
+                }
+                data.journal.insert(version, block);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Writes the journaled ops to the remote, in order. Called by every sync, ops written by
+    /// `apply_ops` while the remote was unavailable are flushed then.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    pub async fn flush_journal(self: &Arc<Self>) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        let journal_lock = self.journal_lock.lock().await;
+
+        let actor = self.shared.try_with(|shared| shared.local_actor())?;
+
+        loop {
+            let next = self.data.with(|data| {
+                data.journal
+                    .first_key_value()
+                    .map(|(version, block)| (*version, block.clone()))
+            });
+            let Some((version, block)) = next else {
+                break;
+            };
+
+            self.storage.store_ops(actor, version, block).await?;
+            self.storage.remove_journal_op(version).await?;
+
+            self.data.with(|data| data.journal.remove(&version));
+        }
+
+        mem::drop(journal_lock);
+
+        Ok(())
+    }
+
+    /// Number of local op blocks that are applied but not yet written to the remote
+    pub fn pending_ops(self: &Arc<Self>) -> usize {
+        self.data.with(|data| data.journal.len())
+    }
+
+    /// Returns all remote blocks that are skipped, because they could not be decrypted or
+    /// decoded.
+    pub fn quarantined(self: &Arc<Self>) -> Vec<QuarantinedBlock> {
+        self.data
+            .with(|data| data.quarantine.values().cloned().collect())
+    }
+
+    /// Op blocks that were given up on with `remove_quarantined` and are not contained in a
+    /// merged state of another device, yet
+    pub fn skipped_ops(self: &Arc<Self>) -> Vec<BlockId> {
+        self.data.with(|data| {
+            data.skipped_ops
+                .iter()
+                .map(|&(actor, version)| BlockId::Op { actor, version })
+                .collect()
+        })
+    }
+
+    /// Releases all quarantined blocks and reads the remote again. Blocks that still fail get
+    /// quarantined again.
+    pub async fn retry_quarantined(self: &Arc<Self>) -> Result<()> {
+        self.data.with(|data| data.quarantine.clear());
+        self.read_remote().await
+    }
 
-                ops_read = true;
+    /// Gives up on a quarantined block.
+    ///
+    /// A state file gets removed from the remote. An op is skipped, so the following ops of that
+    /// actor can be read; the op file itself is left in place for the other devices. The local
+    /// state doesn't contain the skipped op, so compacting fails with `Error::SkippedOps` until
+    /// a state of another device that contains it is merged, see `skipped_ops`.
+    pub async fn remove_quarantined(self: &Arc<Self>, id: &BlockId) -> Result<()> {
+        match id {
+            BlockId::State { name } => {
+                self.ensure_writable()?;
+                self.storage.remove_states(vec![name.clone()]).await?;
+            }
+            BlockId::Op { actor, version } => {
+                self.data.with(|data| {
+                    if data.state.next_op_versions.get(actor) == *version {
+                        data.skipped_ops.insert((*actor, *version));
+                        let version_inc = data.state.next_op_versions.inc(*actor);
+                        data.state.next_op_versions.apply(version_inc);
+                    }
+                });
             }
+        }
 
-            Ok(ops_read)
-        })?;
+        self.data.with(|data| data.quarantine.remove(id));
 
-        Ok(ops_read)
+        self.read_remote().await
     }
 
     async fn read_remote_meta(self: &Arc<Self>) -> Result<()> {
         self.read_remote_meta_(false).await
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
     async fn read_remote_meta_(self: &Arc<Self>, force_notify: bool) -> Result<()> {
-        let names = self
-            .storage
-            .list_remote_meta_names()
-            .await
-            .context("failed getting remote meta entry names while reading remote metas")?;
+        let names = self.storage.list_remote_meta_names().await?;
 
-        let remote_metas_to_read = self.data.with(|data| {
+        let remote_metas_to_read = self.shared.with(|shared| {
             let remote_metas_to_read: Vec<_> = names
                 .into_iter()
-                .filter(|name| !data.read_remote_metas.contains(name))
+                .filter(|name| !shared.read_remote_metas.contains(name))
                 .collect();
             remote_metas_to_read
         });
@@ -568,26 +2622,28 @@ where
         let remote_metas = self
             .storage
             .load_remote_metas(remote_metas_to_read)
-            .await
-            .context("failed loading remote meta while reading remote metas")?
+            .await?
             .into_iter()
             .map(|(name, vbox)| {
                 vbox.ensure_versions_phf(&SUPPORTED_VERSIONS)?;
 
-                let remote_meta: RemoteMeta = rmp_serde::from_slice(vbox.as_ref())?;
+                let remote_meta: RemoteMeta =
+                    rmp_serde::from_slice(vbox.as_ref()).map_err(|err| {
+                        Error::from(err).in_file(RemoteFile::Meta { name: name.clone() })
+                    })?;
 
                 Ok((name, remote_meta))
             })
             .collect::<Result<Vec<_>>>()?;
 
         let remote_meta = if !remote_metas.is_empty() {
-            self.data.with(|data| {
+            self.shared.with(|shared| {
                 for (name, meta) in remote_metas {
-                    data.remote_meta.merge(meta);
-                    data.read_remote_metas.insert(name);
+                    shared.remote_meta.merge(meta);
+                    shared.read_remote_metas.insert(name);
                 }
 
-                Some(data.remote_meta.clone())
+                Some(shared.remote_meta.clone())
             })
         } else {
             None
@@ -615,8 +2671,8 @@ where
         self: &Arc<Self>,
         remote_meta: MVReg<VersionBytes, Uuid>,
     ) -> Result<()> {
-        self.data.with(|data| {
-            data.remote_meta.storage.merge(remote_meta);
+        self.shared.with(|shared| {
+            shared.remote_meta.storage.merge(remote_meta);
         });
 
         self.store_remote_meta().await
@@ -626,8 +2682,8 @@ where
         self: &Arc<Self>,
         remote_meta: MVReg<VersionBytes, Uuid>,
     ) -> Result<()> {
-        self.data.with(|data| {
-            data.remote_meta.cryptor.merge(remote_meta);
+        self.shared.with(|shared| {
+            shared.remote_meta.cryptor.merge(remote_meta);
         });
 
         self.store_remote_meta().await
@@ -637,24 +2693,30 @@ where
         self: &Arc<Self>,
         remote_meta: MVReg<VersionBytes, Uuid>,
     ) -> Result<()> {
-        self.data.with(|data| {
-            data.remote_meta.key_cryptor.merge(remote_meta);
+        self.shared.with(|shared| {
+            shared.remote_meta.key_cryptor.merge(remote_meta);
         });
 
         self.store_remote_meta().await
     }
 
     async fn store_remote_meta(self: &Arc<Self>) -> Result<()> {
-        let vbox = self.data.try_with(|data| {
-            let bytes = rmp_serde::to_vec_named(&data.remote_meta)?;
+        self.ensure_writable()?;
+
+        let vbox = self.shared.try_with(|shared| {
+            let bytes = rmp_serde::to_vec_named(&shared.remote_meta)?;
             Ok(VersionBytes::new(CURRENT_VERSION, bytes))
         })?;
 
         let new_name = self.storage.store_remote_meta(vbox).await?;
 
-        let names_to_remove = self.data.with(|data| {
-            let names_to_remove = data.read_remote_metas.drain().collect();
-            data.read_remote_metas.insert(new_name);
+        let names_to_remove = self.shared.with(|shared| {
+            let names_to_remove = shared
+                .read_remote_metas
+                .drain()
+                .filter(|name| *name != new_name)
+                .collect();
+            shared.read_remote_metas.insert(new_name);
             names_to_remove
         });
 
@@ -663,77 +2725,372 @@ where
         Ok(())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(document = ?self.document))
+    )]
+    async fn encrypt_block(
+        self: &Arc<Self>,
+        key: &Key,
+        clear_text: Vec<u8>,
+        target: Option<BlockTarget>,
+    ) -> Result<VersionBytes> {
+        #[cfg(feature = "tracing")]
+        let (start, clear_text_len) = (Instant::now(), clear_text.len());
+
+        let data_enc = self.cryptor.encrypt(key.key(), clear_text).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            key = %key.id(),
+            clear_text_len,
+            elapsed = ?start.elapsed(),
+            "encrypted block"
+        );
+
+        let signature = target
+            .map(|target| {
+                self.shared.try_with(|shared| {
+                    signing::sign(
+                        shared.local_actor()?,
+                        shared.signing_key()?,
+                        self.document,
+                        target,
+                        key.id(),
+                        &data_enc,
+                    )
+                })
+            })
+            .transpose()?;
+
+        let block = Block {
+            key_id: key.id(),
+            data_enc,
+            signature,
+        };
+        let block = rmp_serde::to_vec_named(&block)?;
+
+        Ok(VersionBytes::new(BLOCK_VERSION, block))
+    }
+
+    /// Decrypts an op or state block with the key referenced by the block, returns the key id and
+    /// the clear text. The signature is checked if the block is stored as `target`, for an
+    /// unsigned state the clock it needs to be covered by is returned as well.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(document = ?self.document))
+    )]
+    async fn decrypt_block(
+        self: &Arc<Self>,
+        block: VersionBytes,
+        target: Option<BlockTarget>,
+    ) -> Result<(Uuid, Vec<u8>, Option<VClock<Uuid>>)> {
+        block.ensure_versions_phf(&SUPPORTED_BLOCK_VERSIONS)?;
+
+        let (key, data_enc, signature) = if block.version() == BLOCK_VERSION {
+            let block: Block = rmp_serde::from_slice(block.as_ref())?;
+            let key = self
+                .shared
+                .try_with(|shared| Ok(shared.keys()?.try_get_key(block.key_id)?))?;
+            (key, block.data_enc, block.signature)
+        } else {
+            let key = self.shared.try_with(|shared| shared.latest_key())?;
+            (key, block.into(), None)
+        };
+
+        let unsigned_bound = match target {
+            Some(target) => {
+                let (published, local_actor, local_key) = self.shared.try_with(|shared| {
+                    Ok((
+                        shared.keys()?.actor_keys(),
+                        shared.local_actor()?,
+                        shared.signing_key()?.verifying_key().to_bytes().to_vec(),
+                    ))
+                })?;
+                let actor_keys = self.data.with(|data| {
+                    let pins = data.signing_key_pins.len();
+                    // a key published by someone else for the local actor is never used
+                    let local_pin = data.signing_key_pins.insert(local_actor, local_key.clone());
+                    let actor_keys =
+                        ActorKeys::new(&published, self.document, &mut data.signing_key_pins);
+                    // keys are only pinned, never replaced
+                    data.signing_key_pins_changed |= local_pin.as_ref() != Some(&local_key)
+                        || data.signing_key_pins.len() != pins;
+                    actor_keys
+                });
+                actor_keys.verify(target, key.id(), &data_enc, signature.as_ref())?
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+
+        let clear_text = self.cryptor.decrypt(key.key(), data_enc).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            key = %key.id(),
+            clear_text_len = clear_text.len(),
+            elapsed = ?start.elapsed(),
+            "decrypted block"
+        );
+
+        Ok((key.id(), clear_text, unsigned_bound))
+    }
+
+    /// Applies `ops` as one block and writes it to the remote. The block is journaled locally
+    /// first, if the remote is unavailable it's written by the next sync, see `pending_ops`.
     pub async fn apply_ops(self: &Arc<Self>, ops: Vec<S::Op>) -> Result<()> {
         // don't allow concurrent op applies
         let apply_ops_lock = self.apply_ops_lock.lock().await;
 
+        let event = self.apply_ops_locked(ops).await?;
+
+        // release lock by hand to prevent an early release by accident
+        mem::drop(apply_ops_lock);
+
+        self.local_ops_applied(event).await
+    }
+
+    /// Derives ops from the current state with `f` and applies them like `apply_ops`. `f` gets
+    /// the state and the local actor. No other local ops are applied between reading the state
+    /// and applying the derived ops. Remote states and ops can still be merged in between, the
+    /// derived ops are applied on top of them; CRDT ops commute, but `f` must not rely on the
+    /// state it got being the one its ops are applied to. Returning no ops doesn't write
+    /// anything.
+    ///
+    /// Locks cores data while `f` runs, do not call other `Core` fns from it.
+    pub async fn update<F>(self: &Arc<Self>, f: F) -> Result<()>
+    where
+        F: FnOnce(&S, Uuid) -> Result<Vec<S::Op>>,
+    {
+        let apply_ops_lock = self.apply_ops_lock.lock().await;
+
+        let actor = self.shared.try_with(|shared| shared.local_actor())?;
+        let ops = self.data.try_with(|data| f(&data.state.state, actor))?;
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        let event = self.apply_ops_locked(ops).await?;
+
+        // release lock by hand to prevent an early release by accident
+        mem::drop(apply_ops_lock);
+
+        self.local_ops_applied(event).await
+    }
+
+    async fn local_ops_applied(self: &Arc<Self>, event: ChangeEvent) -> Result<()> {
+        self.subscribers.notify(event);
+
+        if self.flush_journal().await.is_err() {
+            // the remote is not available, the ops stay in the journal until the next sync. The
+            // journal is replayed on top of the cache, so the cache needs to contain the ops.
+            self.store_cache().await;
+            return Ok(());
+        }
+
+        self.maybe_compact().await;
+
+        Ok(())
+    }
+
+    /// Journals and applies `ops` as one block, `apply_ops_lock` needs to be held
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(document = ?self.document))
+    )]
+    async fn apply_ops_locked(self: &Arc<Self>, ops: Vec<S::Op>) -> Result<ChangeEvent> {
+        if self.closed.load(atomic::Ordering::SeqCst) {
+            return Err(Error::Closed);
+        }
+        self.ensure_writable()?;
+
+        let actor = self.shared.try_with(|shared| shared.local_actor())?;
+        let behind_journal = self.data.with(|data| {
+            data.journal
+                .last_key_value()
+                .is_some_and(|(&version, _)| version >= data.state.next_op_versions.get(&actor))
+        });
+        if behind_journal {
+            // the cached state is behind the journal (see `load_journal`), the journal is
+            // flushed and the earlier ops of the local actor are read before writing new ones
+            self.read_remote_().await?;
+        }
+        // other devices can only check the signature of the block once the key is published
+        self.try_publish_signing_key().await;
+
         let clear_text = rmp_serde::to_vec_named(&ops)?;
         let clear_text = VersionBytes::new(self.current_data_version, clear_text);
 
-        let key = self.data.with(|data| {
-            data.keys
-                .as_ref()
-                .unwrap()
-                .val
-                .latest_key()
-                .context("no latest key")
+        let key = self.shared.try_with(|shared| {
+            if shared.remote_meta.is_retired(actor, self.document) {
+                return Err(Error::RetiredActor { actor });
+            }
+            shared.latest_key()
         })?;
 
-        let data_enc = self
-            .cryptor
-            .encrypt(key.key(), clear_text.serialize())
-            .await
-            .unwrap();
+        // stable, concurrent op applies are prevented by the `apply_ops_lock`
+        let version = self
+            .data
+            .with(|data| data.state.next_op_versions.get(&actor));
 
-        // TODO: add key id
-        // let block = Block {
-        //     data_version: self.current_data_version,
-        //     key_id: Uuid::nil(),
-        //     data_enc,
-        // };
+        let target = BlockTarget::Op { actor, version };
+        let block = self
+            .encrypt_block(&key, clear_text.serialize(), Some(target))
+            .await?;
 
-        let data_enc = VersionBytes::new(CURRENT_VERSION, data_enc);
+        let size = block.as_ref().len() as u64;
 
-        let (actor, version) = self.data.try_with(|data| {
-            let actor = data
-                .local_meta
-                .as_ref()
-                .ok_or_else(|| Error::msg("local meta not loaded"))?
-                .local_actor_id;
-            let version = data.state.next_op_versions.get(&actor);
-            Ok((actor, version))
-        })?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(%actor, version, bytes = size, "applying op block");
 
-        self.storage.store_ops(actor, version, data_enc).await?;
+        self.storage
+            .store_journal_op(version, block.clone())
+            .await?;
 
-        self.data.with(|data| {
+        let clock = self.data.with(|data| {
             for op in ops {
                 data.state.state.apply(op);
             }
 
             let version_inc = data.state.next_op_versions.inc(actor);
             data.state.next_op_versions.apply(version_inc);
+
+            data.op_blocks.insert(
+                (actor, version),
+                OpBlockInfo {
+                    key_id: key.id(),
+                    size,
+                },
+            );
+            data.journal.insert(version, block);
+
+            data.state.next_op_versions.clone()
         });
 
-        // release lock by hand to prevent an early release by accident
-        mem::drop(apply_ops_lock);
+        Ok(ChangeEvent {
+            kind: ChangeKind::Local,
+            states: Vec::new(),
+            ops: vec![(actor, version)],
+            quarantined: Vec::new(),
+            clock,
+        })
+    }
+}
 
-        Ok(())
+impl<S, ST, C, KC> OpenDocument for Core<S, ST, C, KC>
+where
+    S: 'static
+        + CmRDT
+        + CvRDT
+        + Default
+        + Serialize
+        + DeserializeOwned
+        + Clone
+        + Debug
+        + Send
+        + Sync,
+    <S as CmRDT>::Op: 'static + Serialize + DeserializeOwned + Clone + Send,
+    ST: Storage,
+    C: Cryptor,
+    KC: KeyCryptor,
+{
+    fn used_key_ids(&self) -> HashSet<Uuid> {
+        self.data.with(|data| {
+            data.state_infos
+                .values()
+                .map(|info| info.key_id)
+                .chain(data.op_blocks.values().map(|info| info.key_id))
+                .collect()
+        })
+    }
+
+    fn op_clock(&self) -> VClock<Uuid> {
+        self.data.with(|data| data.read_clock())
+    }
+
+    fn close_document(self: Arc<Self>, compact: bool) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            if self.closed.load(atomic::Ordering::SeqCst) {
+                return Ok(());
+            }
+            self.close(compact).await
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
     }
 }
 
-pub struct OpenOptions<ST, C, KC> {
+/// Whether a state or op with the op clock `clock` was read by every actor. Retired actors that
+/// were pruned from `current` (the local op clock) are read completely.
+fn is_removable(
+    clock: &VClock<Uuid>,
+    removable: &VClock<Uuid>,
+    retired_actors: &HashMap<Uuid, u64>,
+    current: &VClock<Uuid>,
+) -> bool {
+    clock.iter().all(|dot| {
+        removable.get(dot.actor) >= dot.counter
+            || (retired_actors.contains_key(dot.actor) && current.get(dot.actor) == 0)
+    })
+}
+
+/// Whether a block failed because its key or the key of its signer isn't synced yet. Such
+/// blocks are read again by the next sync instead of being quarantined.
+fn is_retryable(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::NoKey | Error::UnknownKey(_) | Error::UnknownSigner { .. }
+    )
+}
+
+fn downcast_document<S, ST, C, KC>(
+    id: Uuid,
+    document: Arc<dyn OpenDocument>,
+) -> Result<Arc<Core<S, ST, C, KC>>>
+where
+    S: 'static + CmRDT + Send + Sync,
+    ST: Storage,
+    C: Cryptor,
+    KC: KeyCryptor,
+{
+    document
+        .into_any()
+        .downcast()
+        .map_err(|_| Error::DocumentTypeMismatch { id })
+}
+
+pub struct OpenOptions<S, ST, C, KC>
+where
+    S: CmRDT,
+{
     pub storage: ST,
     pub cryptor: C,
     pub key_cryptor: KC,
     pub create: bool,
     pub supported_data_versions: Vec<Uuid>,
     pub current_data_version: Uuid,
+    pub migrations: Migrations<S>,
+    pub compaction_policy: CompactionPolicy,
+    /// Never write to the local or remote storage. Writing ops and compacting fails with
+    /// `Error::ReadOnly`, opening fails with `Error::NoKey` if there is no key yet.
+    pub read_only: bool,
+    /// Accept a remote that presents less than this device has seen of it before (e.g. restored
+    /// from a backup) on the first read of the remote after opening the repository or a document,
+    /// instead of failing with `Error::RolledBack`.
+    pub accept_rollback: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LocalMeta {
     pub(crate) local_actor_id: Uuid,
+    /// Secret key the local actor signs its blocks with
+    #[serde(default, with = "serde_bytes")]
+    pub(crate) signing_key: Option<Vec<u8>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -742,11 +3099,103 @@ pub(crate) struct StateWrapper<S> {
     pub(crate) state: S,
 }
 
+/// Decrypted state of a document, cached locally by `Core::store_cache`
+#[derive(Debug, Deserialize)]
+struct LocalCache<S> {
+    state: StateWrapper<S>,
+    state_infos: HashMap<String, StateInfo>,
+    op_blocks: Vec<(Uuid, u64, OpBlockInfo)>,
+    #[serde(default)]
+    skipped_ops: BTreeSet<(Uuid, u64)>,
+}
+
+/// Borrowed `LocalCache` for serialization
+#[derive(Debug, Serialize)]
+struct LocalCacheRef<'a, S> {
+    state: &'a StateWrapper<S>,
+    state_infos: &'a HashMap<String, StateInfo>,
+    op_blocks: Vec<(Uuid, u64, &'a OpBlockInfo)>,
+    skipped_ops: &'a BTreeSet<(Uuid, u64)>,
+}
+
+/// Envelope of every op and state file, tells the reader which key to decrypt `data_enc` with
+#[derive(Debug, Serialize, Deserialize)]
+struct Block {
+    key_id: Uuid,
+    #[serde(with = "serde_bytes")]
+    data_enc: Vec<u8>,
+    /// Missing for blocks written before blocks were signed
+    #[serde(default)]
+    signature: Option<BlockSignature>,
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct RemoteMeta {
     storage: MVReg<VersionBytes, Uuid>,
     cryptor: MVReg<VersionBytes, Uuid>,
     key_cryptor: MVReg<VersionBytes, Uuid>,
+    #[serde(default)]
+    retired_actors: GSet<RetiredActor>,
+    #[serde(default)]
+    read_clocks: ReadClocks,
+    #[serde(default)]
+    key_acks: KeyAcks,
+}
+
+impl RemoteMeta {
+    /// Retired actors of `document` with the number of ops they wrote
+    fn retired_actors(&self, document: Option<Uuid>) -> HashMap<Uuid, u64> {
+        let mut retired_actors = HashMap::new();
+        for retired in self.retired_actors.read() {
+            if retired.document == document {
+                let versions = retired_actors.entry(retired.actor).or_insert(0);
+                *versions = retired.versions.max(*versions);
+            }
+        }
+        retired_actors
+    }
+
+    fn is_retired(&self, actor: Uuid, document: Option<Uuid>) -> bool {
+        self.retired_actors
+            .read()
+            .iter()
+            .any(|retired| retired.actor == actor && retired.document == document)
+    }
+
+    /// Whether every not retired actor of `document` acknowledged that it no longer encrypts
+    /// with `key_id`, and every actor has read the ops written until then. `clock` is the local
+    /// op clock of the document.
+    fn key_replaced(&self, key_id: Uuid, document: Option<Uuid>, clock: &VClock<Uuid>) -> bool {
+        let retired_actors = self.retired_actors(document);
+        let removable = self
+            .read_clocks
+            .removable(document, &retired_actors, clock.clone());
+
+        clock
+            .iter()
+            .map(|dot| *dot.actor)
+            .chain(self.read_clocks.actors(document))
+            .filter(|actor| !retired_actors.contains_key(actor))
+            .all(|actor| {
+                self.key_acks
+                    .get(actor, document, key_id)
+                    .is_some_and(|ack| {
+                        // retired actors are pruned from the clocks
+                        ack.iter().all(|dot| {
+                            retired_actors.contains_key(dot.actor)
+                                || dot.counter <= removable.get(dot.actor)
+                        })
+                    })
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct RetiredActor {
+    actor: Uuid,
+    document: Option<Uuid>,
+    /// Number of ops of the actor contained in the state compacted before the retirement
+    versions: u64,
 }
 
 impl CvRDT for RemoteMeta {
@@ -760,6 +3209,130 @@ impl CvRDT for RemoteMeta {
         self.storage.merge(other.storage);
         self.cryptor.merge(other.cryptor);
         self.key_cryptor.merge(other.key_cryptor);
+        self.retired_actors.merge(other.retired_actors);
+        self.read_clocks.merge(other.read_clocks);
+        self.key_acks.merge(other.key_acks);
+    }
+}
+
+/// Op clock of the merged state of every actor per document, the files covered by the clocks of
+/// all (not retired) actors can be removed safely, see `Core::compact`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct ReadClocks(BTreeMap<(Uuid, Option<Uuid>), VClock<Uuid>>);
+
+impl ReadClocks {
+    fn get(&self, actor: Uuid, document: Option<Uuid>) -> Option<&VClock<Uuid>> {
+        self.0.get(&(actor, document))
+    }
+
+    /// Actors that recorded a read clock for `document`
+    fn actors(&self, document: Option<Uuid>) -> impl Iterator<Item = Uuid> + '_ {
+        self.0
+            .keys()
+            .filter(move |(_, doc)| *doc == document)
+            .map(|(actor, _)| *actor)
+    }
+
+    /// Returns `true` if the clock advanced
+    fn record(&mut self, actor: Uuid, document: Option<Uuid>, clock: VClock<Uuid>) -> bool {
+        let read_clock = self.0.entry((actor, document)).or_default();
+        if clock <= *read_clock {
+            return false;
+        }
+        read_clock.merge(clock);
+        true
+    }
+
+    /// Lowest clock of `clock` and the read clocks of the not retired actors of `document`. An
+    /// actor of `clock` without a read clock (e.g. one that never compacted or read a state) has
+    /// read nothing.
+    fn removable(
+        &self,
+        document: Option<Uuid>,
+        retired_actors: &HashMap<Uuid, u64>,
+        mut clock: VClock<Uuid>,
+    ) -> VClock<Uuid> {
+        let unknown_reader = clock.iter().any(|dot| {
+            !retired_actors.contains_key(dot.actor) && !self.0.contains_key(&(*dot.actor, document))
+        });
+        if unknown_reader {
+            return VClock::new();
+        }
+
+        let read_clocks = self
+            .0
+            .iter()
+            .filter(|((actor, doc), _)| *doc == document && !retired_actors.contains_key(actor))
+            .map(|(_, read_clock)| read_clock);
+        for read_clock in read_clocks {
+            clock = clock
+                .iter()
+                .map(|dot| Dot::new(*dot.actor, dot.counter.min(read_clock.get(dot.actor))))
+                .filter(|dot| dot.counter > 0)
+                .fold(VClock::new(), |mut glb, dot| {
+                    glb.apply(dot);
+                    glb
+                });
+        }
+        clock
+    }
+}
+
+impl CvRDT for ReadClocks {
+    type Validation = Infallible;
+
+    fn validate_merge(&self, _other: &Self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (key, clock) in other.0 {
+            self.0.entry(key).or_default().merge(clock);
+        }
+    }
+}
+
+/// Keys the actors no longer encrypt with, per document. Maps `(actor, document, key_id)` to the
+/// op clock of the actor when it acknowledged the key, its blocks encrypted with the key are all
+/// contained in it. See `Core::retire_unused_keys`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct KeyAcks(BTreeMap<(Uuid, Option<Uuid>, Uuid), VClock<Uuid>>);
+
+impl KeyAcks {
+    fn get(&self, actor: Uuid, document: Option<Uuid>, key_id: Uuid) -> Option<&VClock<Uuid>> {
+        self.0.get(&(actor, document, key_id))
+    }
+
+    /// Returns `true` if one of `key_ids` was acknowledged for the first time
+    fn record(
+        &mut self,
+        actor: Uuid,
+        document: Option<Uuid>,
+        key_ids: Vec<Uuid>,
+        clock: &VClock<Uuid>,
+    ) -> bool {
+        let mut recorded = false;
+        for key_id in key_ids {
+            self.0.entry((actor, document, key_id)).or_insert_with(|| {
+                recorded = true;
+                clock.clone()
+            });
+        }
+        recorded
+    }
+}
+
+impl CvRDT for KeyAcks {
+    type Validation = Infallible;
+
+    fn validate_merge(&self, _other: &Self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (key, clock) in other.0 {
+            self.0.entry(key).or_default().merge(clock);
+        }
     }
 }
 
@@ -773,3 +3346,141 @@ impl Info {
         self.actor
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: Uuid = Uuid::from_u128(1);
+    const B: Uuid = Uuid::from_u128(2);
+    const C: Uuid = Uuid::from_u128(3);
+    const KEY: Uuid = Uuid::from_u128(10);
+
+    fn clock(dots: &[(Uuid, u64)]) -> VClock<Uuid> {
+        let mut clock = VClock::new();
+        for &(actor, counter) in dots {
+            clock.apply(Dot::new(actor, counter));
+        }
+        clock
+    }
+
+    fn read_clocks(clocks: &[(Uuid, VClock<Uuid>)]) -> ReadClocks {
+        let mut read_clocks = ReadClocks::default();
+        for (actor, clock) in clocks {
+            read_clocks.record(*actor, None, clock.clone());
+        }
+        read_clocks
+    }
+
+    fn retired(actor: Uuid) -> HashMap<Uuid, u64> {
+        HashMap::from([(actor, 1)])
+    }
+
+    #[test]
+    fn removable_is_lowest_read_clock() {
+        let read_clocks =
+            read_clocks(&[(A, clock(&[(A, 3), (B, 2)])), (B, clock(&[(A, 2), (B, 4)]))]);
+
+        let removable = read_clocks.removable(None, &HashMap::new(), clock(&[(A, 3), (B, 4)]));
+        assert_eq!(removable, clock(&[(A, 2), (B, 2)]));
+
+        // other documents don't count
+        let removable = read_clocks.removable(Some(KEY), &HashMap::new(), clock(&[(A, 3), (B, 4)]));
+        assert_eq!(removable, VClock::new());
+    }
+
+    #[test]
+    fn removable_unknown_reader() {
+        let read_clocks = read_clocks(&[(A, clock(&[(A, 3), (B, 2)]))]);
+
+        // `B` wrote ops, but never recorded what it read
+        let removable = read_clocks.removable(None, &HashMap::new(), clock(&[(A, 3), (B, 2)]));
+        assert_eq!(removable, VClock::new());
+    }
+
+    #[test]
+    fn removable_ignores_retired() {
+        let read_clocks = read_clocks(&[
+            (A, clock(&[(A, 3), (B, 2)])),
+            (B, clock(&[(A, 3), (B, 2)])),
+            // stale, from before the retirement
+            (C, clock(&[(A, 1)])),
+        ]);
+
+        let removable = read_clocks.removable(None, &retired(C), clock(&[(A, 3), (B, 2), (C, 1)]));
+        assert_eq!(removable, clock(&[(A, 3), (B, 2)]));
+    }
+
+    #[test]
+    fn removable_concurrent_clocks() {
+        let read_clocks = read_clocks(&[(A, clock(&[(A, 5)])), (B, clock(&[(B, 5)]))]);
+
+        let removable = read_clocks.removable(None, &HashMap::new(), clock(&[(A, 5), (B, 5)]));
+        assert_eq!(removable, VClock::new());
+    }
+
+    fn new_remote_meta(read: &[(Uuid, VClock<Uuid>)], acks: &[(Uuid, VClock<Uuid>)]) -> RemoteMeta {
+        let mut remote_meta = RemoteMeta {
+            read_clocks: read_clocks(read),
+            ..RemoteMeta::default()
+        };
+        for (actor, clock) in acks {
+            remote_meta.key_acks.record(*actor, None, vec![KEY], clock);
+        }
+        remote_meta
+    }
+
+    #[test]
+    fn key_replaced_after_acks_and_reads() {
+        let read = [(A, clock(&[(A, 3), (B, 2)])), (B, clock(&[(A, 3), (B, 2)]))];
+        let local_clock = clock(&[(A, 3), (B, 2)]);
+
+        let remote_meta = new_remote_meta(
+            &read,
+            &[(A, clock(&[(A, 2), (B, 1)])), (B, clock(&[(A, 1), (B, 2)]))],
+        );
+        assert!(remote_meta.key_replaced(KEY, None, &local_clock));
+        assert!(!remote_meta.key_replaced(KEY, Some(KEY), &local_clock));
+
+        // `B` still encrypts with the key
+        let remote_meta = new_remote_meta(&read, &[(A, clock(&[(A, 2), (B, 1)]))]);
+        assert!(!remote_meta.key_replaced(KEY, None, &local_clock));
+    }
+
+    #[test]
+    fn key_replaced_unknown_reader() {
+        let remote_meta = new_remote_meta(
+            &[(A, clock(&[(A, 3), (B, 2)]))],
+            &[(A, clock(&[(A, 2)])), (B, clock(&[(B, 2)]))],
+        );
+
+        assert!(!remote_meta.key_replaced(KEY, None, &clock(&[(A, 3), (B, 2)])));
+    }
+
+    #[test]
+    fn key_replaced_ignores_retired() {
+        let mut remote_meta = new_remote_meta(
+            &[(A, clock(&[(A, 3), (B, 2)])), (B, clock(&[(A, 3), (B, 2)]))],
+            &[(A, clock(&[(A, 2)])), (B, clock(&[(B, 2)]))],
+        );
+        remote_meta.retired_actors.insert(RetiredActor {
+            actor: C,
+            document: None,
+            versions: 1,
+        });
+
+        // `C` neither read nor acknowledged anything
+        assert!(remote_meta.key_replaced(KEY, None, &clock(&[(A, 3), (B, 2), (C, 1)])));
+    }
+
+    #[test]
+    fn key_replaced_concurrent_clocks() {
+        let remote_meta = new_remote_meta(
+            &[(A, clock(&[(A, 3)])), (B, clock(&[(B, 2)]))],
+            &[(A, clock(&[(A, 1)])), (B, clock(&[(B, 1)]))],
+        );
+
+        // neither read the ops of the other one
+        assert!(!remote_meta.key_replaced(KEY, None, &clock(&[(A, 3), (B, 2)])));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{Result, StateWrapper};
+use ::crdts::CmRDT;
+use ::serde::de::DeserializeOwned;
+use ::std::{collections::HashMap, fmt, sync::Arc};
+use ::uuid::Uuid;
+
+type DecodeState<S> = Arc<dyn Fn(&[u8]) -> Result<StateWrapper<S>> + Send + Sync>;
+type DecodeOps<O> = Arc<dyn Fn(&[u8]) -> Result<Vec<O>> + Send + Sync>;
+
+/// Decoders for states and ops written with older data versions. Blocks of these versions are
+/// upgraded into the current state and op types when they are read, the next compaction writes
+/// them in the current data version. Blocks of supported versions without a migration are decoded
+/// as the current types.
+pub struct Migrations<S>
+where
+    S: CmRDT,
+{
+    migrations: HashMap<Uuid, Migration<S>>,
+}
+
+struct Migration<S>
+where
+    S: CmRDT,
+{
+    state: DecodeState<S>,
+    ops: DecodeOps<S::Op>,
+}
+
+impl<S> Migrations<S>
+where
+    S: CmRDT,
+{
+    pub fn new() -> Migrations<S> {
+        Migrations {
+            migrations: HashMap::new(),
+        }
+    }
+
+    /// Registers a migration from the data `version`, whose states were `OS` and ops were `OO`.
+    /// The version is supported implicitly, it doesn't need to be in
+    /// `OpenOptions::supported_data_versions`.
+    pub fn add<OS, OO, FS, FO>(mut self, version: Uuid, migrate_state: FS, migrate_op: FO) -> Self
+    where
+        OS: DeserializeOwned,
+        OO: DeserializeOwned,
+        FS: 'static + Fn(OS) -> Result<S> + Send + Sync,
+        FO: 'static + Fn(OO) -> Result<S::Op> + Send + Sync,
+    {
+        let state: DecodeState<S> = Arc::new(move |bytes| {
+            let old: StateWrapper<OS> = rmp_serde::from_slice(bytes)?;
+            Ok(StateWrapper {
+                next_op_versions: old.next_op_versions,
+                state: migrate_state(old.state)?,
+            })
+        });
+        let ops: DecodeOps<S::Op> = Arc::new(move |bytes| {
+            let old: Vec<OO> = rmp_serde::from_slice(bytes)?;
+            old.into_iter().map(&migrate_op).collect()
+        });
+
+        self.migrations.insert(version, Migration { state, ops });
+        self
+    }
+
+    pub(crate) fn versions(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.migrations.keys().copied()
+    }
+
+    /// `None` if there is no migration for `version`
+    pub(crate) fn decode_state(
+        &self,
+        version: Uuid,
+        bytes: &[u8],
+    ) -> Option<Result<StateWrapper<S>>> {
+        let migration = self.migrations.get(&version)?;
+        Some((migration.state)(bytes))
+    }
+
+    /// `None` if there is no migration for `version`
+    pub(crate) fn decode_ops(&self, version: Uuid, bytes: &[u8]) -> Option<Result<Vec<S::Op>>> {
+        let migration = self.migrations.get(&version)?;
+        Some((migration.ops)(bytes))
+    }
+}
+
+impl<S> Default for Migrations<S>
+where
+    S: CmRDT,
+{
+    fn default() -> Migrations<S> {
+        Migrations::new()
+    }
+}
+
+impl<S> Clone for Migrations<S>
+where
+    S: CmRDT,
+{
+    fn clone(&self) -> Migrations<S> {
+        let migrations = self
+            .migrations
+            .iter()
+            .map(|(version, migration)| {
+                let migration = Migration {
+                    state: migration.state.clone(),
+                    ops: migration.ops.clone(),
+                };
+                (*version, migration)
+            })
+            .collect();
+        Migrations { migrations }
+    }
+}
+
+impl<S> fmt::Debug for Migrations<S>
+where
+    S: CmRDT,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Migrations")
+            .field("versions", &self.migrations.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use ::crdts::{GSet, VClock};
+
+    const OLD_VERSION: Uuid = Uuid::from_u128(0x5b7e02a4_1c39_4f8d_a6e0_d2f4c81b9e37);
+    const ACTOR: Uuid = Uuid::from_u128(0x0c6f3d28_7a41_4b95_8e2d_f15a9c7b6403);
+
+    fn migrations() -> Migrations<GSet<String>> {
+        Migrations::new().add(
+            OLD_VERSION,
+            |old: Vec<u32>| {
+                let mut state = GSet::new();
+                for value in old {
+                    state.insert(value.to_string());
+                }
+                Ok(state)
+            },
+            |old: u32| {
+                if old == 0 {
+                    return Err(Error::decode("zero is not allowed"));
+                }
+                Ok(old.to_string())
+            },
+        )
+    }
+
+    #[test]
+    fn decode_state() {
+        let mut next_op_versions = VClock::new();
+        next_op_versions.apply(next_op_versions.inc(ACTOR));
+        let old = StateWrapper {
+            next_op_versions: next_op_versions.clone(),
+            state: vec![1u32, 2],
+        };
+        let bytes = rmp_serde::to_vec_named(&old).unwrap();
+
+        let state = migrations()
+            .decode_state(OLD_VERSION, &bytes)
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.next_op_versions, next_op_versions);
+        assert!(state.state.contains(&"1".to_owned()));
+        assert!(state.state.contains(&"2".to_owned()));
+    }
+
+    #[test]
+    fn decode_ops() {
+        let bytes = rmp_serde::to_vec_named(&vec![1u32, 2]).unwrap();
+        let ops = migrations()
+            .decode_ops(OLD_VERSION, &bytes)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ops, ["1", "2"]);
+
+        let bytes = rmp_serde::to_vec_named(&vec![1u32, 0]).unwrap();
+        let res = migrations().decode_ops(OLD_VERSION, &bytes).unwrap();
+        assert!(matches!(res, Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn unknown_version() {
+        let bytes = rmp_serde::to_vec_named(&vec![1u32]).unwrap();
+        assert!(migrations().decode_ops(Uuid::nil(), &bytes).is_none());
+        assert!(migrations().decode_state(Uuid::nil(), &bytes).is_none());
+    }
+
+    #[test]
+    fn invalid_data() {
+        let bytes = rmp_serde::to_vec_named(&"not a list").unwrap();
+        let res = migrations().decode_ops(OLD_VERSION, &bytes).unwrap();
+        assert!(matches!(res, Err(Error::Decode(_))));
+    }
+}
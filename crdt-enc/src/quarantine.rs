@@ -0,0 +1,41 @@
+use crate::Error;
+use ::serde::Serialize;
+use ::std::{fmt, sync::Arc};
+use ::uuid::Uuid;
+
+/// Identifies a state or op file in the remote storage
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum BlockId {
+    State { name: String },
+    Op { actor: Uuid, version: u64 },
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockId::State { name } => write!(f, "state {}", name),
+            BlockId::Op { actor, version } => write!(f, "op {} of actor {}", version, actor),
+        }
+    }
+}
+
+/// A remote block that could not be decrypted or decoded. It is skipped while reading the remote
+/// until it is retried (`Core::retry_quarantined`) or removed (`Core::remove_quarantined`).
+///
+/// A quarantined op blocks all later ops of the same actor, because ops need to be applied in
+/// order.
+#[derive(Debug, Clone)]
+pub struct QuarantinedBlock {
+    pub(crate) id: BlockId,
+    pub(crate) cause: Arc<Error>,
+}
+
+impl QuarantinedBlock {
+    pub fn id(&self) -> &BlockId {
+        &self.id
+    }
+
+    pub fn cause(&self) -> &Error {
+        &self.cause
+    }
+}
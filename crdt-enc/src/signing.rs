@@ -0,0 +1,495 @@
+use crate::{Error, Result};
+use ::crdts::{CmRDT, Dot, VClock};
+use ::ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ::serde::{Deserialize, Serialize};
+use ::std::collections::{BTreeMap, HashMap};
+use ::uuid::Uuid;
+
+/// Where a block is stored, part of the signed data so a signed block can't be moved to another
+/// actor, version or document
+#[derive(Debug, Clone, Copy, Serialize)]
+pub(crate) enum BlockTarget {
+    State,
+    Op { actor: Uuid, version: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BlockSignature {
+    /// The signing actor, for ops the actor of the op
+    pub(crate) actor: Uuid,
+    #[serde(with = "serde_bytes")]
+    pub(crate) signature: Vec<u8>,
+}
+
+/// Public signing key of an actor, published through the key cryptor (`Keys`) for every
+/// document the actor writes to, so only members of the repository can publish keys
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct ActorKey {
+    pub(crate) actor: Uuid,
+    pub(crate) document: Option<Uuid>,
+    #[serde(with = "serde_bytes")]
+    pub(crate) public_key: Vec<u8>,
+    /// Ops of the actor before this version were written before the key was published and are
+    /// not signed
+    pub(crate) first_version: u64,
+    /// Op clock of the document when the key was published. Unsigned blocks of actors without a
+    /// key are only accepted below the lowest of these clocks, they were written before the
+    /// first key was published.
+    pub(crate) unsigned_versions: BTreeMap<Uuid, u64>,
+}
+
+#[derive(Serialize)]
+struct SignedData<'a> {
+    document: Option<Uuid>,
+    target: BlockTarget,
+    key_id: Uuid,
+    #[serde(with = "serde_bytes")]
+    data_enc: &'a [u8],
+}
+
+pub(crate) fn gen_signing_key() -> Result<Vec<u8>> {
+    let mut secret = [0; 32];
+    getrandom::getrandom(&mut secret).map_err(Error::encrypt)?;
+    Ok(secret.to_vec())
+}
+
+pub(crate) fn signing_key_from_bytes(bytes: &[u8]) -> Result<SigningKey> {
+    let secret = bytes
+        .try_into()
+        .map_err(|_| Error::decode("invalid signing key length"))?;
+    Ok(SigningKey::from_bytes(secret))
+}
+
+pub(crate) fn sign(
+    actor: Uuid,
+    signing_key: &SigningKey,
+    document: Option<Uuid>,
+    target: BlockTarget,
+    key_id: Uuid,
+    data_enc: &[u8],
+) -> Result<BlockSignature> {
+    let data = rmp_serde::to_vec_named(&SignedData {
+        document,
+        target,
+        key_id,
+        data_enc,
+    })?;
+    Ok(BlockSignature {
+        actor,
+        signature: signing_key.sign(&data).to_bytes().to_vec(),
+    })
+}
+
+/// Published keys of the actors of a document. Every device pins the key it first saw for an
+/// actor (trust on first use), keys published for the actor later are ignored. As long as an
+/// actor has more than one key and none is pinned, blocks signed with any of them are accepted.
+pub(crate) struct ActorKeys {
+    document: Option<Uuid>,
+    /// Public keys and first signed version by actor
+    keys: HashMap<Uuid, (Vec<VerifyingKey>, u64)>,
+    /// `None` as long as no key is published, see `ActorKey::unsigned_versions`
+    unsigned_clock: Option<VClock<Uuid>>,
+}
+
+impl ActorKeys {
+    /// `pins` are the pinned public keys by actor, the key of an actor with a single published
+    /// key gets pinned
+    pub(crate) fn new(
+        actor_keys: &[ActorKey],
+        document: Option<Uuid>,
+        pins: &mut HashMap<Uuid, Vec<u8>>,
+    ) -> ActorKeys {
+        let actor_keys: Vec<_> = actor_keys
+            .iter()
+            .filter(|key| key.document == document)
+            .collect();
+
+        let mut published: HashMap<Uuid, Vec<(&ActorKey, VerifyingKey)>> = HashMap::new();
+        for &actor_key in &actor_keys {
+            let public_key = <[u8; 32]>::try_from(actor_key.public_key.as_slice())
+                .ok()
+                .and_then(|public_key| VerifyingKey::from_bytes(&public_key).ok());
+            // invalid keys are ignored, so they can't be pinned
+            if let Some(public_key) = public_key {
+                published
+                    .entry(actor_key.actor)
+                    .or_default()
+                    .push((actor_key, public_key));
+            }
+        }
+
+        let keys = published
+            .into_iter()
+            .map(|(actor, mut published)| {
+                match pins.get(&actor) {
+                    Some(pinned) => published.retain(|(key, _)| key.public_key == *pinned),
+                    None if published
+                        .iter()
+                        .all(|(key, _)| key.public_key == published[0].0.public_key) =>
+                    {
+                        pins.insert(actor, published[0].0.public_key.clone());
+                    }
+                    None => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(%actor, ?document, "actor has several signing keys");
+                    }
+                }
+
+                let first_version = published
+                    .iter()
+                    .map(|(key, _)| key.first_version)
+                    .min()
+                    .unwrap_or(0);
+                let public_keys = published
+                    .into_iter()
+                    .map(|(_, public_key)| public_key)
+                    .collect();
+                (actor, (public_keys, first_version))
+            })
+            .collect();
+
+        let unsigned_clock = actor_keys
+            .iter()
+            .map(|key| &key.unsigned_versions)
+            .fold(None, |glb: Option<BTreeMap<Uuid, u64>>, versions| {
+                Some(match glb {
+                    None => versions.clone(),
+                    Some(glb) => glb
+                        .into_iter()
+                        .filter_map(|(actor, version)| {
+                            Some((actor, version.min(*versions.get(&actor)?)))
+                        })
+                        .collect(),
+                })
+            })
+            .map(|glb| {
+                let mut clock = VClock::new();
+                for (actor, version) in glb {
+                    if version > 0 {
+                        clock.apply(Dot::new(actor, version));
+                    }
+                }
+                clock
+            });
+
+        ActorKeys {
+            document,
+            keys,
+            unsigned_clock,
+        }
+    }
+
+    /// Checks the signature of a block stored as `target`. Unsigned blocks are accepted as long
+    /// as no key is published, afterwards only if they were written before: ops of an actor
+    /// before its first signed version, ops of actors without a key and states below the lowest
+    /// published `ActorKey::unsigned_versions`. The op clock of a state is only known after
+    /// decrypting it, so for an unsigned state the clock it needs to be covered by is returned,
+    /// see `ensure_unsigned_state`.
+    ///
+    /// A state isn't bound to the actor that signed it, it contains the ops of every actor. Its
+    /// signature only shows that it was written by an actor with a published key.
+    pub(crate) fn verify(
+        &self,
+        target: BlockTarget,
+        key_id: Uuid,
+        data_enc: &[u8],
+        signature: Option<&BlockSignature>,
+    ) -> Result<Option<VClock<Uuid>>> {
+        let signature = match (target, signature) {
+            (BlockTarget::Op { actor, .. }, Some(signature)) if signature.actor != actor => {
+                return Err(Error::InvalidSignature);
+            }
+            (_, Some(signature)) => signature,
+            (BlockTarget::Op { actor, version }, None) => {
+                return match self.keys.get(&actor) {
+                    Some((_, first_version)) if version < *first_version => Ok(None),
+                    Some(_) => Err(Error::InvalidSignature),
+                    None if self
+                        .unsigned_clock
+                        .as_ref()
+                        .is_none_or(|clock| version < clock.get(&actor)) =>
+                    {
+                        Ok(None)
+                    }
+                    // the key of the actor might not be synced, yet
+                    None => Err(Error::UnknownSigner { actor }),
+                };
+            }
+            (BlockTarget::State, None) => return Ok(self.unsigned_clock.clone()),
+        };
+
+        let Some((public_keys, _)) = self.keys.get(&signature.actor) else {
+            return Err(Error::UnknownSigner {
+                actor: signature.actor,
+            });
+        };
+
+        let data = rmp_serde::to_vec_named(&SignedData {
+            document: self.document,
+            target,
+            key_id,
+            data_enc,
+        })?;
+        let signature =
+            Signature::from_slice(&signature.signature).map_err(|_| Error::InvalidSignature)?;
+        if public_keys
+            .iter()
+            .any(|public_key| public_key.verify(&data, &signature).is_ok())
+        {
+            Ok(None)
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+}
+
+/// Checks that an unsigned state with the op clock `clock` was written before the first key
+/// was published, `bound` is returned by `ActorKeys::verify`
+pub(crate) fn ensure_unsigned_state(
+    bound: Option<&VClock<Uuid>>,
+    clock: &VClock<Uuid>,
+) -> Result<()> {
+    match bound {
+        Some(bound) if !clock.iter().all(|dot| dot.counter <= bound.get(dot.actor)) => {
+            Err(Error::InvalidSignature)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: Uuid = Uuid::from_u128(1);
+    const B: Uuid = Uuid::from_u128(2);
+    const KEY: Uuid = Uuid::from_u128(10);
+    const DATA: &[u8] = b"data";
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn actor_key(
+        actor: Uuid,
+        signing_key: &SigningKey,
+        first_version: u64,
+        unsigned_versions: &[(Uuid, u64)],
+    ) -> ActorKey {
+        ActorKey {
+            actor,
+            document: None,
+            public_key: signing_key.verifying_key().to_bytes().to_vec(),
+            first_version,
+            unsigned_versions: unsigned_versions.iter().copied().collect(),
+        }
+    }
+
+    fn op(actor: Uuid, version: u64) -> BlockTarget {
+        BlockTarget::Op { actor, version }
+    }
+
+    fn clock(dots: &[(Uuid, u64)]) -> VClock<Uuid> {
+        let mut clock = VClock::new();
+        for &(actor, version) in dots {
+            clock.apply(Dot::new(actor, version));
+        }
+        clock
+    }
+
+    #[test]
+    fn valid_signature() {
+        let key = signing_key(1);
+        let actor_keys = ActorKeys::new(&[actor_key(A, &key, 0, &[])], None, &mut HashMap::new());
+
+        let signature = sign(A, &key, None, op(A, 0), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Ok(None)));
+
+        let signature = sign(A, &key, None, BlockTarget::State, KEY, DATA).unwrap();
+        let res = actor_keys.verify(BlockTarget::State, KEY, DATA, Some(&signature));
+        assert!(matches!(res, Ok(None)));
+    }
+
+    #[test]
+    fn bad_signature() {
+        let key = signing_key(1);
+        let actor_keys = ActorKeys::new(&[actor_key(A, &key, 0, &[])], None, &mut HashMap::new());
+
+        let mut signature = sign(A, &key, None, op(A, 0), KEY, DATA).unwrap();
+        signature.signature[0] ^= 1;
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+
+        // signed for another version, key or document
+        let signature = sign(A, &key, None, op(A, 1), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+        let signature = sign(A, &key, None, op(A, 0), Uuid::from_u128(11), DATA).unwrap();
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+        let signature = sign(A, &key, Some(B), op(A, 0), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+
+        // an op of another actor
+        let signature = sign(A, &key, None, op(B, 0), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(B, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+
+        // an actor without a key
+        let signature = sign(B, &signing_key(2), None, op(B, 0), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(B, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::UnknownSigner { actor }) if actor == B));
+    }
+
+    #[test]
+    fn single_key_gets_pinned() {
+        let key = signing_key(1);
+        let mut pins = HashMap::new();
+        ActorKeys::new(&[actor_key(A, &key, 0, &[])], None, &mut pins);
+        assert_eq!(
+            pins,
+            HashMap::from([(A, actor_key(A, &key, 0, &[]).public_key)])
+        );
+
+        // several keys and none pinned, nothing to pin
+        let mut pins = HashMap::new();
+        let published = [
+            actor_key(A, &key, 0, &[]),
+            actor_key(A, &signing_key(2), 0, &[]),
+        ];
+        let actor_keys = ActorKeys::new(&published, None, &mut pins);
+        assert!(pins.is_empty());
+        for key in [signing_key(1), signing_key(2)] {
+            let signature = sign(A, &key, None, op(A, 0), KEY, DATA).unwrap();
+            let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+            assert!(matches!(res, Ok(None)));
+        }
+    }
+
+    #[test]
+    fn wrong_pinned_key() {
+        let pinned = signing_key(1);
+        let published = signing_key(2);
+        let mut pins = HashMap::from([(A, actor_key(A, &pinned, 0, &[]).public_key)]);
+        let actor_keys = ActorKeys::new(&[actor_key(A, &published, 0, &[])], None, &mut pins);
+        assert_eq!(
+            pins,
+            HashMap::from([(A, actor_key(A, &pinned, 0, &[]).public_key)])
+        );
+
+        let signature = sign(A, &published, None, op(A, 0), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+
+        // a later published key for the pinned one is ignored, too
+        let published = [
+            actor_key(A, &pinned, 0, &[]),
+            actor_key(A, &signing_key(3), 0, &[]),
+        ];
+        let actor_keys = ActorKeys::new(&published, None, &mut pins);
+        let signature = sign(A, &signing_key(3), None, op(A, 0), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+        let signature = sign(A, &pinned, None, op(A, 0), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Ok(None)));
+    }
+
+    #[test]
+    fn unsigned_above_bound() {
+        let key = signing_key(1);
+        let published = [actor_key(A, &key, 2, &[(A, 2), (B, 3)])];
+        let actor_keys = ActorKeys::new(&published, None, &mut HashMap::new());
+
+        // ops of an actor with a key before its first signed version
+        assert!(matches!(
+            actor_keys.verify(op(A, 1), KEY, DATA, None),
+            Ok(None)
+        ));
+        let res = actor_keys.verify(op(A, 2), KEY, DATA, None);
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+
+        // ops of an actor without a key below the published clock
+        assert!(matches!(
+            actor_keys.verify(op(B, 2), KEY, DATA, None),
+            Ok(None)
+        ));
+        let res = actor_keys.verify(op(B, 3), KEY, DATA, None);
+        assert!(matches!(res, Err(Error::UnknownSigner { actor }) if actor == B));
+        let res = actor_keys.verify(op(Uuid::from_u128(3), 0), KEY, DATA, None);
+        assert!(matches!(res, Err(Error::UnknownSigner { .. })));
+
+        // states need to be covered by the published clock
+        let bound = actor_keys
+            .verify(BlockTarget::State, KEY, DATA, None)
+            .unwrap();
+        assert_eq!(bound, Some(clock(&[(A, 2), (B, 3)])));
+        assert!(ensure_unsigned_state(bound.as_ref(), &clock(&[(A, 2), (B, 3)])).is_ok());
+        let res = ensure_unsigned_state(bound.as_ref(), &clock(&[(A, 2), (B, 4)]));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+        let res = ensure_unsigned_state(bound.as_ref(), &clock(&[(Uuid::from_u128(3), 1)]));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+    }
+
+    #[test]
+    fn unsigned_bound_is_lowest_published_clock() {
+        let published = [
+            actor_key(A, &signing_key(1), 0, &[(A, 2), (B, 3)]),
+            actor_key(B, &signing_key(2), 3, &[(A, 4), (B, 1)]),
+        ];
+        let actor_keys = ActorKeys::new(&published, None, &mut HashMap::new());
+        let bound = actor_keys
+            .verify(BlockTarget::State, KEY, DATA, None)
+            .unwrap();
+        assert_eq!(bound, Some(clock(&[(A, 2), (B, 1)])));
+
+        // no key published, everything unsigned is accepted
+        let actor_keys = ActorKeys::new(&[], None, &mut HashMap::new());
+        assert!(matches!(
+            actor_keys.verify(op(A, 5), KEY, DATA, None),
+            Ok(None)
+        ));
+        let bound = actor_keys
+            .verify(BlockTarget::State, KEY, DATA, None)
+            .unwrap();
+        assert_eq!(bound, None);
+        assert!(ensure_unsigned_state(bound.as_ref(), &clock(&[(A, 5)])).is_ok());
+    }
+
+    #[test]
+    fn signed_below_bound() {
+        let key = signing_key(1);
+        let published = [actor_key(A, &key, 5, &[(A, 5)])];
+        let actor_keys = ActorKeys::new(&published, None, &mut HashMap::new());
+
+        let signature = sign(A, &key, None, op(A, 2), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 2), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Ok(None)));
+
+        // the signature is still checked
+        let signature = sign(A, &signing_key(2), None, op(A, 2), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 2), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::InvalidSignature)));
+
+        // a signed state doesn't need to be covered by the bound
+        let signature = sign(A, &key, None, BlockTarget::State, KEY, DATA).unwrap();
+        let res = actor_keys.verify(BlockTarget::State, KEY, DATA, Some(&signature));
+        assert!(matches!(res, Ok(None)));
+    }
+
+    #[test]
+    fn other_documents_are_ignored() {
+        let key = signing_key(1);
+        let mut published = actor_key(A, &key, 0, &[]);
+        published.document = Some(B);
+        let mut pins = HashMap::new();
+        let actor_keys = ActorKeys::new(&[published], None, &mut pins);
+        assert!(pins.is_empty());
+
+        let signature = sign(A, &key, None, op(A, 0), KEY, DATA).unwrap();
+        let res = actor_keys.verify(op(A, 0), KEY, DATA, Some(&signature));
+        assert!(matches!(res, Err(Error::UnknownSigner { .. })));
+    }
+}
@@ -1,8 +1,8 @@
-use crate::{CoreSubHandle, utils::VersionBytes};
-use ::anyhow::Result;
+use crate::{CoreSubHandle, Error, Result, utils::VersionBytes};
 use ::async_trait::async_trait;
 use ::crdts::MVReg;
-use ::std::fmt::Debug;
+use ::futures::stream::BoxStream;
+use ::std::{fmt::Debug, ops::Range};
 use ::uuid::Uuid;
 
 #[async_trait]
@@ -18,26 +18,149 @@ where
         Ok(())
     }
 
+    /// Called by `Core::close`, after the last write
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns a stream of changes to the remote, `None` if the storage can't watch for changes.
+    /// Events should only be emitted after the changed files are complete.
+    fn watch(&self) -> Option<BoxStream<'static, Result<StorageEvent>>> {
+        None
+    }
+
+    /// Returns the storage of the document `id`. It shares the local and the remote meta with
+    /// `self`, but has its own states and ops.
+    fn document(&self, _id: Uuid) -> Result<Self> {
+        Err(Error::Unsupported("documents"))
+    }
+
+    /// Lists the ids of all documents, besides the default document
+    async fn list_documents(&self) -> Result<Vec<Uuid>> {
+        Ok(Vec::new())
+    }
+
     async fn load_local_meta(&self) -> Result<Option<VersionBytes>>;
     async fn store_local_meta(&self, data: VersionBytes) -> Result<()>;
 
+    /// Loads the local cache of the document, see `store_local_cache`
+    async fn load_local_cache(&self) -> Result<Option<VersionBytes>> {
+        Ok(None)
+    }
+
+    /// Stores the encrypted state of the document, so it doesn't need to be read from the remote
+    /// again on the next open. It's only read by this device. The default impl doesn't cache.
+    async fn store_local_cache(&self, _data: VersionBytes) -> Result<()> {
+        Ok(())
+    }
+
+    /// Loads the high-water mark of the document, see `store_local_high_water`
+    async fn load_local_high_water(&self) -> Result<Option<VersionBytes>> {
+        Ok(None)
+    }
+
+    /// Stores the op clock of everything this device has seen of the document in the remote,
+    /// used to detect a rolled back remote. It's only read by this device and should be written
+    /// atomically. The default impl doesn't persist it, a rollback is only detected while the
+    /// process runs.
+    async fn store_local_high_water(&self, _data: VersionBytes) -> Result<()> {
+        Ok(())
+    }
+
+    /// Loads the pinned signing keys of the document, see `store_local_key_pins`
+    async fn load_local_key_pins(&self) -> Result<Option<VersionBytes>> {
+        Ok(None)
+    }
+
+    /// Stores the public signing key of each actor, pinned when this device first saw it. It's
+    /// only read by this device and should be written atomically. The default impl doesn't
+    /// persist them, after a restart the keys are pinned again.
+    async fn store_local_key_pins(&self, _data: VersionBytes) -> Result<()> {
+        Ok(())
+    }
+
+    /// Loads the journaled local op blocks, see `store_journal_op`. Like for `load_states`, an
+    /// entry that can't be parsed is returned as `Err`.
+    async fn load_journal(&self) -> Result<Vec<(u64, Result<VersionBytes>)>> {
+        Ok(Vec::new())
+    }
+
+    /// Stores an op block of the local actor before it's written to the remote with
+    /// `store_ops`, so it isn't lost while the remote is unavailable. The entry should be
+    /// written atomically, a torn last entry is dropped on load. Needs `store_local_cache`
+    /// to be supported as well. The default impl doesn't persist the journal, pending ops are
+    /// lost when the process exits.
+    async fn store_journal_op(&self, _version: u64, _data: VersionBytes) -> Result<()> {
+        Ok(())
+    }
+
+    /// Removes a journaled op block after it was written to the remote
+    async fn remove_journal_op(&self, _version: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the name `data` gets when it's stored as a state or remote meta file, used by
+    /// `Core::verify`. `None` if the names are not derived from the content.
+    fn content_name(&self, _data: &VersionBytes) -> Option<String> {
+        None
+    }
+
     async fn list_remote_meta_names(&self) -> Result<Vec<String>>;
     async fn load_remote_metas(&self, names: Vec<String>) -> Result<Vec<(String, VersionBytes)>>;
     async fn store_remote_meta(&self, data: VersionBytes) -> Result<String>;
     async fn remove_remote_metas(&self, names: Vec<String>) -> Result<()>;
 
     async fn list_state_names(&self) -> Result<Vec<String>>;
-    async fn load_states(&self, names: Vec<String>) -> Result<Vec<(String, VersionBytes)>>;
+    /// A file that can't be parsed is returned as `Err` (e.g. `Error::Decode`), so the other
+    /// files are still read
+    async fn load_states(&self, names: Vec<String>) -> Result<Vec<(String, Result<VersionBytes>)>>;
     async fn store_state(&self, data: VersionBytes) -> Result<String>;
     async fn remove_states(&self, names: Vec<String>) -> Result<Vec<String>>;
 
     async fn list_op_actors(&self) -> Result<Vec<Uuid>>;
+    /// Lists the versions of all stored ops of `actor`, in any order. Needed by `Core::verify`,
+    /// `Core::debug_dump`, `archive::export` and the rollback check of `Core::read_remote`,
+    /// without it the check only covers the states.
+    async fn list_op_versions(&self, _actor: Uuid) -> Result<Vec<u64>> {
+        Err(Error::Unsupported("listing op versions"))
+    }
 
-    /// needs to return the ops ordered by version of that actor
+    /// needs to return the ops ordered by version of that actor. Like for `load_states`, a file
+    /// that can't be parsed is returned as `Err`.
     async fn load_ops(
         &self,
         actor_first_versions: Vec<(Uuid, u64)>,
-    ) -> Result<Vec<(Uuid, u64, VersionBytes)>>;
+    ) -> Result<Vec<(Uuid, u64, Result<VersionBytes>)>>;
+    /// Storing the same op again, e.g. after an interrupted journal flush, needs to succeed
     async fn store_ops(&self, actor: Uuid, version: u64, data: VersionBytes) -> Result<()>;
-    async fn remove_ops(&self, actor_last_verions: Vec<(Uuid, u64)>) -> Result<()>;
+    /// Removes all ops of every actor within the given version range
+    async fn remove_ops(&self, actor_versions: Vec<(Uuid, Range<u64>)>) -> Result<()>;
+}
+
+/// Loads all stored ops of `actor`, ordered by version. `load_ops` stops at a gap, so every
+/// consecutive run of versions is loaded on its own.
+pub(crate) async fn load_all_ops<ST: Storage>(
+    storage: &ST,
+    actor: Uuid,
+) -> Result<Vec<(Uuid, u64, Result<VersionBytes>)>> {
+    let mut versions = storage.list_op_versions(actor).await?;
+    versions.sort_unstable();
+
+    let run_starts = versions
+        .iter()
+        .enumerate()
+        .filter(|&(i, &version)| i == 0 || versions[i - 1] + 1 != version)
+        .map(|(_, &version)| (actor, version))
+        .collect();
+
+    let mut ops = storage.load_ops(run_starts).await?;
+    ops.sort_unstable_by_key(|&(_, version, _)| version);
+    Ok(ops)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StorageEvent {
+    Meta,
+    States,
+    Ops { actor: Uuid },
 }
@@ -0,0 +1,148 @@
+use crate::{Result, storage::StorageEvent};
+
+/// Options for `Core::run`. Intervals are counted in sync rounds, one round per tick or batch of
+/// storage events.
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Read the remote meta every `n`th round, `1` reads it every round
+    pub remote_meta_interval: u32,
+    /// Compact every `n`th round, additionally to the `CompactionPolicy`
+    pub compact_interval: Option<u32>,
+    /// Upper bound of ticks that are skipped after a failed round. The number of skipped ticks
+    /// doubles with every consecutive failure.
+    pub max_backoff: u32,
+}
+
+impl Default for SyncOptions {
+    fn default() -> SyncOptions {
+        SyncOptions {
+            remote_meta_interval: 1,
+            compact_interval: None,
+            max_backoff: 32,
+        }
+    }
+}
+
+pub(crate) enum SyncTrigger {
+    Tick,
+    Storage(Vec<Result<StorageEvent>>),
+    /// `ticks` ended
+    Stop,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SyncState {
+    pub(crate) round: u64,
+    failures: u32,
+    skip: u32,
+}
+
+impl SyncState {
+    /// Returns `true` if this tick should be skipped because of the backoff
+    pub(crate) fn skip_tick(&mut self) -> bool {
+        if self.skip == 0 {
+            return false;
+        }
+        self.skip -= 1;
+        true
+    }
+
+    /// Whether ticks are skipped because of the backoff
+    pub(crate) fn backing_off(&self) -> bool {
+        self.skip > 0
+    }
+
+    pub(crate) fn round_done(&mut self, success: bool, options: &SyncOptions) {
+        self.round += 1;
+        if success {
+            self.failures = 0;
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            self.skip = 1u32
+                .checked_shl(self.failures - 1)
+                .unwrap_or(u32::MAX)
+                .min(options.max_backoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skipped_ticks(state: &mut SyncState) -> u32 {
+        let mut skipped = 0;
+        while state.skip_tick() {
+            skipped += 1;
+        }
+        skipped
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let options = SyncOptions::default();
+        let mut state = SyncState::default();
+
+        for expected in [1, 2, 4, 8, 16] {
+            state.round_done(false, &options);
+            assert_eq!(skipped_ticks(&mut state), expected);
+        }
+        assert_eq!(state.round, 5);
+    }
+
+    #[test]
+    fn backoff_capped() {
+        let options = SyncOptions {
+            max_backoff: 3,
+            ..SyncOptions::default()
+        };
+        let mut state = SyncState::default();
+
+        for expected in [1, 2, 3, 3, 3] {
+            state.round_done(false, &options);
+            assert_eq!(skipped_ticks(&mut state), expected);
+        }
+    }
+
+    #[test]
+    fn success_resets_backoff() {
+        let options = SyncOptions::default();
+        let mut state = SyncState::default();
+
+        state.round_done(false, &options);
+        state.round_done(false, &options);
+        assert_eq!(skipped_ticks(&mut state), 2);
+
+        state.round_done(true, &options);
+        assert_eq!(skipped_ticks(&mut state), 0);
+
+        state.round_done(false, &options);
+        assert_eq!(skipped_ticks(&mut state), 1);
+    }
+
+    #[test]
+    fn backing_off_until_skipped() {
+        let options = SyncOptions::default();
+        let mut state = SyncState::default();
+        assert!(!state.backing_off());
+
+        state.round_done(false, &options);
+        assert!(state.backing_off());
+        assert!(state.skip_tick());
+        assert!(!state.backing_off());
+    }
+
+    #[test]
+    fn many_failures_dont_overflow() {
+        let options = SyncOptions {
+            max_backoff: u32::MAX,
+            ..SyncOptions::default()
+        };
+        let mut state = SyncState::default();
+
+        for _ in 0..40 {
+            state.round_done(false, &options);
+        }
+        assert_eq!(state.skip, u32::MAX);
+    }
+}
@@ -2,9 +2,9 @@ mod version_bytes;
 
 pub use version_bytes::*;
 
-use ::anyhow::{Context, Result};
+use crate::Result;
 use ::crdts::{CmRDT, CvRDT, MVReg, ctx::ReadCtx};
-use ::futures::{Future, FutureExt, StreamExt, TryStreamExt, stream};
+use ::futures::{Future, StreamExt, TryStreamExt, stream};
 use ::serde::{Deserialize, Serialize, de::DeserializeOwned};
 use ::std::{convert::Infallible, fmt::Debug, sync::Mutex as SyncMutex};
 use ::uuid::Uuid;
@@ -43,12 +43,10 @@ pub fn decode_version_bytes_mvreg<T: DeserializeOwned + CvRDT + Default>(
         .into_iter()
         .try_fold(T::default(), |mut acc, vb| -> Result<T> {
             vb.ensure_versions(supported_versions)?;
-            let keys =
-                rmp_serde::from_slice(vb.as_ref()).context("Could not parse msgpack value")?;
+            let keys = rmp_serde::from_slice(vb.as_ref())?;
             acc.merge(keys);
             Ok(acc)
-        })
-        .context("Could not process mvreg value")?;
+        })?;
     Ok(ReadCtx {
         add_clock: read_ctx.add_clock,
         rm_clock: read_ctx.rm_clock,
@@ -73,17 +71,14 @@ where
             vb.ensure_versions(supported_versions)?;
             Ok(vb.into())
         })
-        .map_ok(|buf| {
-            buf_decode(buf).map(|res| res.context("Custom buffer decode function failed"))
-        })
+        .map_ok(&mut buf_decode)
         .try_buffer_unordered(16)
         .try_fold(T::default(), |mut acc, buf| async move {
-            let keys = rmp_serde::from_slice(&buf).context("Could not parse msgpack value")?;
+            let keys = rmp_serde::from_slice(&buf)?;
             acc.merge(keys);
             Ok(acc)
         })
-        .await
-        .context("Could not process mvreg value")?;
+        .await?;
     Ok(ReadCtx {
         add_clock: read_ctx.add_clock,
         rm_clock: read_ctx.rm_clock,
@@ -107,17 +102,14 @@ where
             vb.ensure_versions_phf(supported_versions)?;
             Ok(vb.into())
         })
-        .map_ok(|buf| {
-            buf_decode(buf).map(|res| res.context("Custom buffer decode function failed"))
-        })
+        .map_ok(&mut buf_decode)
         .try_buffer_unordered(16)
         .try_fold(T::default(), |mut acc, buf| async move {
-            let keys = rmp_serde::from_slice(&buf).context("Could not parse msgpack value")?;
+            let keys = rmp_serde::from_slice(&buf)?;
             acc.merge(keys);
             Ok(acc)
         })
-        .await
-        .context("Could not process mvreg value")?;
+        .await?;
     Ok(ReadCtx {
         add_clock: read_ctx.add_clock,
         rm_clock: read_ctx.rm_clock,
@@ -132,7 +124,7 @@ pub fn encode_version_bytes_mvreg<T: Serialize>(
     version: Uuid,
 ) -> Result<()> {
     let (val, read_ctx) = val.split();
-    let buf = rmp_serde::to_vec_named(&val).context("Could not serialize value to msgpack")?;
+    let buf = rmp_serde::to_vec_named(&val)?;
     let vb = VersionBytes::new(version, buf);
     let op = reg.write(vb, read_ctx.derive_add_ctx(actor));
     reg.apply(op);
@@ -152,10 +144,8 @@ where
     Fut: Future<Output = Result<Vec<u8>>>,
 {
     let (val, read_ctx) = val.split();
-    let buf = rmp_serde::to_vec_named(&val).context("Could not serialize value to msgpack")?;
-    let buf = buf_encode(buf)
-        .await
-        .context("Custom buffer encode function failed")?;
+    let buf = rmp_serde::to_vec_named(&val)?;
+    let buf = buf_encode(buf).await?;
     let vb = VersionBytes::new(version, buf);
     let op = reg.write(vb, read_ctx.derive_add_ctx(actor));
     reg.apply(op);
@@ -185,7 +175,7 @@ impl<T> LockBox<T> {
         f(&mut *data)
     }
 
-    /// Utility `LockBox::with` function, that enforces a `anyhow::Result` return type.
+    /// Utility `LockBox::with` function, that enforces a `crdt_enc::Result` return type.
     pub fn try_with<F, R>(&self, f: F) -> Result<R>
     where
         F: FnOnce(&mut T) -> Result<R>,
@@ -0,0 +1,63 @@
+use crate::{Error, quarantine::BlockId};
+use ::std::{fmt, ops::Range, sync::Arc};
+use ::uuid::Uuid;
+
+/// Result of `Core::verify`
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub(crate) remote_metas: usize,
+    pub(crate) states: usize,
+    pub(crate) op_blocks: usize,
+    pub(crate) issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    /// Number of checked remote meta files
+    pub fn remote_metas(&self) -> usize {
+        self.remote_metas
+    }
+
+    /// Number of checked state files
+    pub fn states(&self) -> usize {
+        self.states
+    }
+
+    /// Number of checked op files
+    pub fn op_blocks(&self) -> usize {
+        self.op_blocks
+    }
+
+    pub fn issues(&self) -> &[Issue] {
+        &self.issues
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// A remote file checked by `Core::verify`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RemoteFile {
+    Meta { name: String },
+    Block(BlockId),
+}
+
+impl fmt::Display for RemoteFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteFile::Meta { name } => write!(f, "remote meta {}", name),
+            RemoteFile::Block(id) => fmt::Display::fmt(id, f),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Issue {
+    /// The name of a content addressed file doesn't match its content
+    NameMismatch { file: RemoteFile, expected: String },
+    /// The file can't be loaded, decrypted or decoded
+    Unreadable { file: RemoteFile, cause: Arc<Error> },
+    /// Op versions of `actor` that are neither stored nor contained in a readable state
+    OpGap { actor: Uuid, versions: Range<u64> },
+}
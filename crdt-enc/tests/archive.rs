@@ -0,0 +1,89 @@
+mod common;
+
+use common::MemoryStorage;
+use crdt_enc::{Error, archive, storage::Storage, utils::VersionBytes};
+use futures::executor::block_on;
+use uuid::Uuid;
+
+const VERSION: Uuid = Uuid::from_u128(0x6a1d9e42_3b7c_4f05_92e8_c41f7d2a5b96);
+const ACTOR: Uuid = Uuid::from_u128(0xb3e80f17_5d2a_4c69_8a41_0e96c7f3d258);
+const DOCUMENT: Uuid = Uuid::from_u128(0x2f94c6d0_8e13_4a7b_b5c2_7d0a3e19f846);
+
+fn block(content: u8) -> VersionBytes {
+    VersionBytes::new(VERSION, vec![content; 4])
+}
+
+async fn filled_storage() -> MemoryStorage {
+    let storage = MemoryStorage::default();
+    storage.store_remote_meta(block(1)).await.unwrap();
+    storage.store_state(block(2)).await.unwrap();
+    storage.store_state(block(3)).await.unwrap();
+    storage.store_ops(ACTOR, 0, block(4)).await.unwrap();
+    storage.store_ops(ACTOR, 1, block(5)).await.unwrap();
+    // after a gap, `load_ops` stops at it
+    storage.store_ops(ACTOR, 3, block(6)).await.unwrap();
+
+    let document = storage.document(DOCUMENT).unwrap();
+    document.store_state(block(7)).await.unwrap();
+    document.store_ops(ACTOR, 0, block(8)).await.unwrap();
+
+    storage
+}
+
+#[test]
+fn round_trip() {
+    block_on(async {
+        let source = filled_storage().await;
+
+        let archive = archive::export(&source).await.unwrap();
+        let archive = VersionBytes::deserialize(&archive.serialize()).unwrap();
+
+        let info = archive::verify(&archive).unwrap();
+        assert_eq!(info.remote_metas(), 1);
+        assert_eq!(info.documents(), 2);
+        assert_eq!(info.states(), 3);
+        assert_eq!(info.op_blocks(), 4);
+
+        let target = MemoryStorage::default();
+        let info = archive::import(&target, &archive).await.unwrap();
+        assert_eq!(info.op_blocks(), 4);
+
+        assert_eq!(target.snapshot(), source.snapshot());
+    });
+}
+
+#[test]
+fn import_needs_empty_storage() {
+    block_on(async {
+        let archive = archive::export(&filled_storage().await).await.unwrap();
+
+        let target = MemoryStorage::default();
+        target.store_remote_meta(block(1)).await.unwrap();
+        let res = archive::import(&target, &archive).await;
+        assert!(matches!(res, Err(Error::StorageNotEmpty)));
+
+        let target = MemoryStorage::default();
+        let document = target.document(DOCUMENT).unwrap();
+        document.store_ops(ACTOR, 0, block(1)).await.unwrap();
+        let res = archive::import(&target, &archive).await;
+        assert!(matches!(res, Err(Error::StorageNotEmpty)));
+    });
+}
+
+#[test]
+fn corrupt_archive() {
+    block_on(async {
+        let archive = archive::export(&filled_storage().await).await.unwrap();
+
+        let mut bytes = archive.as_ref().to_vec();
+        *bytes.last_mut().unwrap() ^= 1;
+        let archive = VersionBytes::new(archive.version(), bytes);
+
+        assert!(matches!(archive::verify(&archive), Err(Error::Decode(_))));
+
+        let target = MemoryStorage::default();
+        let res = archive::import(&target, &archive).await;
+        assert!(matches!(res, Err(Error::Decode(_))));
+        assert_eq!(target.snapshot().metas.len(), 0);
+    });
+}
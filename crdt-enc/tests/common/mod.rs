@@ -0,0 +1,344 @@
+//! Backends for the integration tests, keeping everything in memory
+
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use crdt_enc::{
+    Core, CoreSubHandle, OpenOptions, Result,
+    cryptor::Cryptor,
+    key_cryptor::{KeyCryptor, Keys},
+    storage::Storage,
+    utils::{
+        LockBox, VersionBytes, VersionBytesRef, decode_version_bytes_mvreg,
+        encode_version_bytes_mvreg,
+    },
+};
+use crdts::{CvRDT, GSet, MVReg, ctx::ReadCtx};
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+const DATA_VERSION: Uuid = Uuid::from_u128(0xd41f0b6e_27c3_4a98_b5e0_9a6c3d12f7e4);
+const KEY_VERSION: Uuid = Uuid::from_u128(0x3e7a5c21_84f6_4b09_9d2e_c60b1f47a853);
+const KEYS_VERSION: Uuid = Uuid::from_u128(0x91c4e2a7_0d58_4f3b_a6e1_5b7f28c9d304);
+
+#[derive(Debug, Default)]
+pub struct Remote {
+    metas: BTreeMap<String, VersionBytes>,
+    documents: BTreeMap<Option<Uuid>, Document>,
+    next_name: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Document {
+    states: BTreeMap<String, VersionBytes>,
+    ops: BTreeMap<(Uuid, u64), VersionBytes>,
+}
+
+/// Remote content of a storage, states and metas sorted by content, as their names differ
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub metas: Vec<Vec<u8>>,
+    pub documents: BTreeMap<Option<Uuid>, DocumentSnapshot>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DocumentSnapshot {
+    pub states: Vec<Vec<u8>>,
+    pub ops: BTreeMap<(Uuid, u64), Vec<u8>>,
+}
+
+/// Storage keeping the remote in memory, shared by the storages of its documents and clones
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStorage {
+    document: Option<Uuid>,
+    remote: Arc<Mutex<Remote>>,
+}
+
+impl MemoryStorage {
+    fn with_document<R>(&self, f: impl FnOnce(&mut Document) -> R) -> R {
+        let mut remote = self.remote.lock().unwrap();
+        f(remote.documents.entry(self.document).or_default())
+    }
+
+    fn next_name(&self) -> String {
+        let mut remote = self.remote.lock().unwrap();
+        remote.next_name += 1;
+        remote.next_name.to_string()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let remote = self.remote.lock().unwrap();
+
+        let mut metas: Vec<_> = remote.metas.values().map(|meta| meta.serialize()).collect();
+        metas.sort();
+
+        let documents = remote
+            .documents
+            .iter()
+            .map(|(id, document)| {
+                let mut states: Vec<_> = document
+                    .states
+                    .values()
+                    .map(|state| state.serialize())
+                    .collect();
+                states.sort();
+                let ops = document
+                    .ops
+                    .iter()
+                    .map(|(id, ops)| (*id, ops.serialize()))
+                    .collect();
+                (*id, DocumentSnapshot { states, ops })
+            })
+            .collect();
+
+        Snapshot { metas, documents }
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    fn document(&self, id: Uuid) -> Result<Self> {
+        Ok(MemoryStorage {
+            document: Some(id),
+            remote: self.remote.clone(),
+        })
+    }
+
+    async fn list_documents(&self) -> Result<Vec<Uuid>> {
+        let remote = self.remote.lock().unwrap();
+        Ok(remote.documents.keys().filter_map(|id| *id).collect())
+    }
+
+    async fn load_local_meta(&self) -> Result<Option<VersionBytes>> {
+        Ok(None)
+    }
+
+    async fn store_local_meta(&self, _data: VersionBytes) -> Result<()> {
+        Ok(())
+    }
+
+    async fn list_remote_meta_names(&self) -> Result<Vec<String>> {
+        let remote = self.remote.lock().unwrap();
+        Ok(remote.metas.keys().cloned().collect())
+    }
+
+    async fn load_remote_metas(&self, names: Vec<String>) -> Result<Vec<(String, VersionBytes)>> {
+        let remote = self.remote.lock().unwrap();
+        Ok(names
+            .into_iter()
+            .filter_map(|name| {
+                let meta = remote.metas.get(&name)?.clone();
+                Some((name, meta))
+            })
+            .collect())
+    }
+
+    async fn store_remote_meta(&self, data: VersionBytes) -> Result<String> {
+        let name = self.next_name();
+        let mut remote = self.remote.lock().unwrap();
+        remote.metas.insert(name.clone(), data);
+        Ok(name)
+    }
+
+    async fn remove_remote_metas(&self, names: Vec<String>) -> Result<()> {
+        let mut remote = self.remote.lock().unwrap();
+        for name in names {
+            remote.metas.remove(&name);
+        }
+        Ok(())
+    }
+
+    async fn list_state_names(&self) -> Result<Vec<String>> {
+        Ok(self.with_document(|document| document.states.keys().cloned().collect()))
+    }
+
+    async fn load_states(&self, names: Vec<String>) -> Result<Vec<(String, Result<VersionBytes>)>> {
+        Ok(self.with_document(|document| {
+            names
+                .into_iter()
+                .filter_map(|name| {
+                    let state = document.states.get(&name)?.clone();
+                    Some((name, Ok(state)))
+                })
+                .collect()
+        }))
+    }
+
+    async fn store_state(&self, data: VersionBytes) -> Result<String> {
+        let name = self.next_name();
+        self.with_document(|document| document.states.insert(name.clone(), data));
+        Ok(name)
+    }
+
+    async fn remove_states(&self, names: Vec<String>) -> Result<Vec<String>> {
+        Ok(self.with_document(|document| {
+            names
+                .into_iter()
+                .filter(|name| document.states.remove(name).is_some())
+                .collect()
+        }))
+    }
+
+    async fn list_op_actors(&self) -> Result<Vec<Uuid>> {
+        Ok(self.with_document(|document| {
+            let mut actors: Vec<_> = document.ops.keys().map(|(actor, _)| *actor).collect();
+            actors.dedup();
+            actors
+        }))
+    }
+
+    async fn list_op_versions(&self, actor: Uuid) -> Result<Vec<u64>> {
+        Ok(self.with_document(|document| {
+            document
+                .ops
+                .range((actor, 0)..=(actor, u64::MAX))
+                .map(|((_, version), _)| *version)
+                .collect()
+        }))
+    }
+
+    async fn load_ops(
+        &self,
+        actor_first_versions: Vec<(Uuid, u64)>,
+    ) -> Result<Vec<(Uuid, u64, Result<VersionBytes>)>> {
+        Ok(self.with_document(|document| {
+            let mut ops = Vec::new();
+            for (actor, mut version) in actor_first_versions {
+                while let Some(block) = document.ops.get(&(actor, version)) {
+                    ops.push((actor, version, Ok(block.clone())));
+                    version += 1;
+                }
+            }
+            ops
+        }))
+    }
+
+    async fn store_ops(&self, actor: Uuid, version: u64, data: VersionBytes) -> Result<()> {
+        self.with_document(|document| document.ops.insert((actor, version), data));
+        Ok(())
+    }
+
+    async fn remove_ops(&self, actor_versions: Vec<(Uuid, Range<u64>)>) -> Result<()> {
+        self.with_document(|document| {
+            document.ops.retain(|(actor, version), _| {
+                !actor_versions
+                    .iter()
+                    .any(|(a, versions)| a == actor && versions.contains(version))
+            })
+        });
+        Ok(())
+    }
+}
+
+/// Opens a core on `storage`, with a state of numbers
+pub async fn open<ST: Storage>(
+    storage: ST,
+    read_only: bool,
+) -> Result<Arc<Core<GSet<u32>, ST, PlainCryptor, PlainKeyCryptor>>> {
+    Core::open(OpenOptions {
+        storage,
+        cryptor: PlainCryptor,
+        key_cryptor: PlainKeyCryptor::default(),
+        create: true,
+        supported_data_versions: vec![DATA_VERSION],
+        current_data_version: DATA_VERSION,
+        migrations: Default::default(),
+        compaction_policy: Default::default(),
+        read_only,
+        accept_rollback: false,
+    })
+    .await
+}
+
+/// Cryptor that doesn't encrypt
+#[derive(Debug, Default)]
+pub struct PlainCryptor;
+
+#[async_trait]
+impl Cryptor for PlainCryptor {
+    async fn gen_key(&self) -> Result<VersionBytes> {
+        Ok(VersionBytes::new(
+            KEY_VERSION,
+            Uuid::new_v4().as_bytes().to_vec(),
+        ))
+    }
+
+    async fn encrypt(&self, _key: VersionBytesRef<'_>, clear_text: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(clear_text)
+    }
+
+    async fn decrypt(&self, _key: VersionBytesRef<'_>, enc_data: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(enc_data)
+    }
+}
+
+/// Key cryptor that stores the keys unencrypted in the remote meta
+#[derive(Debug)]
+pub struct PlainKeyCryptor {
+    data: LockBox<KeyCryptorData>,
+}
+
+#[derive(Debug, Default)]
+struct KeyCryptorData {
+    core: Option<Box<dyn CoreSubHandle>>,
+    remote_meta: MVReg<VersionBytes, Uuid>,
+}
+
+impl Default for PlainKeyCryptor {
+    fn default() -> PlainKeyCryptor {
+        PlainKeyCryptor {
+            data: LockBox::new(KeyCryptorData::default()),
+        }
+    }
+}
+
+impl PlainKeyCryptor {
+    fn core(&self) -> Box<dyn CoreSubHandle> {
+        self.data.with(|data| {
+            dyn_clone::clone_box(&**data.core.as_ref().expect("key cryptor not initialized"))
+        })
+    }
+}
+
+#[async_trait]
+impl KeyCryptor for PlainKeyCryptor {
+    async fn init(&self, core: &dyn CoreSubHandle) -> Result<()> {
+        self.data
+            .with(|data| data.core = Some(dyn_clone::clone_box(core)));
+        Ok(())
+    }
+
+    async fn set_remote_meta(
+        &self,
+        new_remote_meta: Option<MVReg<VersionBytes, Uuid>>,
+    ) -> Result<()> {
+        let remote_meta = self.data.with(|data| {
+            if let Some(new_remote_meta) = new_remote_meta {
+                data.remote_meta.merge(new_remote_meta);
+            }
+            data.remote_meta.clone()
+        });
+
+        let keys_ctx: ReadCtx<Keys, Uuid> =
+            decode_version_bytes_mvreg(&remote_meta, &[KEYS_VERSION])?;
+        self.core().set_keys(keys_ctx).await
+    }
+
+    async fn set_keys(&self, new_keys: ReadCtx<Keys, Uuid>) -> Result<()> {
+        let core = self.core();
+        let mut remote_meta = self.data.with(|data| data.remote_meta.clone());
+        encode_version_bytes_mvreg(
+            &mut remote_meta,
+            new_keys,
+            core.info().actor(),
+            KEYS_VERSION,
+        )?;
+
+        self.set_remote_meta(Some(remote_meta.clone())).await?;
+        core.set_remote_meta_key_cryptor(remote_meta).await
+    }
+}
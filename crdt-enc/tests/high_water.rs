@@ -0,0 +1,122 @@
+mod common;
+
+use async_trait::async_trait;
+use common::MemoryStorage;
+use crdt_enc::{Error, Result, storage::Storage, utils::VersionBytes};
+use futures::executor::block_on;
+use std::ops::Range;
+use uuid::Uuid;
+
+/// Storage that can't list op versions, it uses the default of `Storage::list_op_versions`
+#[derive(Debug, Default)]
+struct DefaultListing(MemoryStorage);
+
+#[async_trait]
+impl Storage for DefaultListing {
+    async fn load_local_meta(&self) -> Result<Option<VersionBytes>> {
+        self.0.load_local_meta().await
+    }
+
+    async fn store_local_meta(&self, data: VersionBytes) -> Result<()> {
+        self.0.store_local_meta(data).await
+    }
+
+    async fn list_remote_meta_names(&self) -> Result<Vec<String>> {
+        self.0.list_remote_meta_names().await
+    }
+
+    async fn load_remote_metas(&self, names: Vec<String>) -> Result<Vec<(String, VersionBytes)>> {
+        self.0.load_remote_metas(names).await
+    }
+
+    async fn store_remote_meta(&self, data: VersionBytes) -> Result<String> {
+        self.0.store_remote_meta(data).await
+    }
+
+    async fn remove_remote_metas(&self, names: Vec<String>) -> Result<()> {
+        self.0.remove_remote_metas(names).await
+    }
+
+    async fn list_state_names(&self) -> Result<Vec<String>> {
+        self.0.list_state_names().await
+    }
+
+    async fn load_states(&self, names: Vec<String>) -> Result<Vec<(String, Result<VersionBytes>)>> {
+        self.0.load_states(names).await
+    }
+
+    async fn store_state(&self, data: VersionBytes) -> Result<String> {
+        self.0.store_state(data).await
+    }
+
+    async fn remove_states(&self, names: Vec<String>) -> Result<Vec<String>> {
+        self.0.remove_states(names).await
+    }
+
+    async fn list_op_actors(&self) -> Result<Vec<Uuid>> {
+        self.0.list_op_actors().await
+    }
+
+    async fn load_ops(
+        &self,
+        actor_first_versions: Vec<(Uuid, u64)>,
+    ) -> Result<Vec<(Uuid, u64, Result<VersionBytes>)>> {
+        self.0.load_ops(actor_first_versions).await
+    }
+
+    async fn store_ops(&self, actor: Uuid, version: u64, data: VersionBytes) -> Result<()> {
+        self.0.store_ops(actor, version, data).await
+    }
+
+    async fn remove_ops(&self, actor_versions: Vec<(Uuid, Range<u64>)>) -> Result<()> {
+        self.0.remove_ops(actor_versions).await
+    }
+}
+
+#[test]
+fn ops_not_listable() {
+    block_on(async {
+        let core = common::open(DefaultListing::default(), false)
+            .await
+            .unwrap();
+        core.apply_ops(vec![1]).await.unwrap();
+
+        // the first read raises the mark above the states, the second one checks the ops
+        core.read_remote().await.unwrap();
+        core.read_remote().await.unwrap();
+        assert!(core.with_state(|state| Ok(state.contains(&1))).unwrap());
+    });
+}
+
+#[test]
+fn removed_ops_before_state_synced() {
+    block_on(async {
+        let storage = MemoryStorage::default();
+
+        let core = common::open(storage.clone(), false).await.unwrap();
+        core.apply_ops(vec![1]).await.unwrap();
+        core.apply_ops(vec![2]).await.unwrap();
+        core.compact().await.unwrap();
+        core.read_remote().await.unwrap();
+
+        // another device replaced the state and removed the ops covered by every read clock,
+        // the removal synced before its state
+        let names = storage.list_state_names().await.unwrap();
+        storage.remove_states(names).await.unwrap();
+        core.read_remote().await.unwrap();
+
+        // ops above the read clock are still required
+        core.apply_ops(vec![3]).await.unwrap();
+        core.read_remote().await.unwrap();
+        let actor = core.info().actor();
+        storage.remove_ops(vec![(actor, 2..3)]).await.unwrap();
+        assert!(matches!(
+            core.read_remote().await,
+            Err(Error::RolledBack {
+                seen: 3,
+                presented: 2,
+                ..
+            })
+        ));
+    });
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use common::MemoryStorage;
+use crdt_enc::Error;
+use futures::executor::block_on;
+
+#[test]
+fn close_without_compacting() {
+    block_on(async {
+        let storage = MemoryStorage::default();
+
+        let core = common::open(storage.clone(), false).await.unwrap();
+        core.apply_ops(vec![1]).await.unwrap();
+        core.close(false).await.unwrap();
+
+        let core = common::open(storage.clone(), true).await.unwrap();
+        core.read_remote().await.unwrap();
+        assert!(core.with_state(|state| Ok(state.contains(&1))).unwrap());
+        assert!(matches!(
+            core.apply_ops(vec![2]).await,
+            Err(Error::ReadOnly)
+        ));
+
+        let snapshot = storage.snapshot();
+        core.close(true).await.unwrap();
+        assert_eq!(storage.snapshot(), snapshot);
+    });
+}
@@ -1,7 +1,9 @@
 use ::anyhow::Result;
+use ::crdt_enc::sync::SyncOptions;
 use ::crdt_enc_gpgme::KeyHandler;
 use ::crdt_enc_tokio::Storage;
 use ::crdt_enc_xchacha20poly1305::EncHandler;
+use ::futures::{stream, TryStreamExt};
 use ::uuid::Uuid;
 
 const CURRENT_DATA_VERSION: Uuid = Uuid::from_u128(0xaadfd5a6_6e19_4b24_a802_4fa27c72f20c);
@@ -22,19 +24,21 @@ async fn main() -> Result<()> {
         create: true,
         supported_data_versions: SUPPORTED_DATA_VERSIONS.iter().cloned().collect(),
         current_data_version: CURRENT_DATA_VERSION,
+        migrations: Default::default(),
+        compaction_policy: Default::default(),
+        read_only: false,
+        accept_rollback: false,
     };
     let repo = crdt_enc::Core::open(open_options).await?;
-    let info = repo.info();
 
     // let actor_id = repo.actor_id();
 
-    // repo.run(futures::stream::empty()).try_for_each(|state| {
-    //     dbg!(state);
-    // });
-
     // dbg!(&repo);
 
-    repo.read_remote().await?;
+    // a single sync round, pass an interval timer stream to keep syncing
+    repo.run(stream::once(async {}), SyncOptions::default())
+        .try_collect::<()>()
+        .await?;
 
     // dbg!(&repo);
 
@@ -42,16 +46,16 @@ async fn main() -> Result<()> {
 
     // dbg!(&repo);
 
-    let op = repo.with_state(|s: &crdts::MVReg<u64, Uuid>| {
+    repo.update(|s: &crdts::MVReg<u64, Uuid>, actor| {
         let read_ctx = s.read();
         let new_val = read_ctx.val.iter().copied().max().unwrap_or(0) + 1;
-        let op = s.write(new_val, read_ctx.derive_add_ctx(info.actor()));
-        Ok(op)
-    })?;
-
-    dbg!(&op);
+        let op = s.write(new_val, read_ctx.derive_add_ctx(actor));
+        dbg!(&op);
+        Ok(vec![op])
+    })
+    .await?;
 
-    repo.apply_ops(vec![op]).await?;
+    repo.close(false).await?;
 
     Ok(())
 }